@@ -1,63 +1,278 @@
-use std::convert::Infallible;
-use std::fmt::Debug;
-use std::mem;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::iter::Product;
+use std::{iter, mem};
 use std::str::FromStr;
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use num_bigint::BigUint;
+use num_traits::Zero;
 
+/// Deepest evaluation stack an operation may need.
+const MAX_STACK: usize = 16;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Instruction {
+    Old,
+    Const(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Arithmetic expression over `old`, compiled to postfix bytecode evaluated on a fixed-size stack
+/// so that the hot loop doesn't allocate.
 #[derive(Debug, Eq, PartialEq, Clone)]
-enum Operation {
-    Square,
-    Add(usize),
-    Multiply(usize),
+pub struct Operation {
+    code: Vec<Instruction>,
 }
 
 impl Operation {
+    /// Worry levels can't be negative, so subtraction saturates at 0.
+    ///
+    /// # Panics
+    ///
+    /// When an intermediate result overflows or is divided by zero, see `exec_checked`.
+    #[inline]
     pub fn exec(&self, level: usize) -> usize {
-        match self {
-            Operation::Square => level * level,
-            Operation::Add(n) => level + *n,
-            Operation::Multiply(n) => level * *n,
+        self.exec_checked(level).expect("worry level out of range")
+    }
+
+    /// `None` when any intermediate result overflows or is divided by zero.
+    #[inline]
+    pub fn exec_checked(&self, level: usize) -> Option<usize> {
+        self.run(level, |instruction, a, b| match instruction {
+            Instruction::Add => a.checked_add(b),
+            Instruction::Sub => Some(a.saturating_sub(b)),
+            Instruction::Mul => a.checked_mul(b),
+            _ => a.checked_div(b),
+        })
+    }
+
+    /// # Panics
+    ///
+    /// When an intermediate result is divided by zero.
+    pub fn exec_big(&self, level: &BigUint) -> BigUint {
+        let mut stack: Vec<BigUint> = vec![];
+        for instruction in &self.code {
+            let value = match instruction {
+                Instruction::Old => level.clone(),
+                Instruction::Const(n) => BigUint::from(*n),
+                op => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    match op {
+                        Instruction::Add => a + b,
+                        Instruction::Sub if a > b => a - b,
+                        Instruction::Sub => BigUint::zero(),
+                        Instruction::Mul => a * b,
+                        _ => a / b,
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap()
+    }
+
+    /// Whether the operation only adds and multiplies, so that reducing worry levels modulo the
+    /// dividers doesn't change the outcome of the tests.
+    pub fn is_modular(&self) -> bool {
+        self.code.iter().all(|instruction| !matches!(instruction, Instruction::Sub | Instruction::Div))
+    }
+
+    #[inline]
+    fn run<F>(&self, level: usize, apply: F) -> Option<usize>
+        where
+            F: Fn(Instruction, usize, usize) -> Option<usize>
+    {
+        let mut stack = [0; MAX_STACK];
+        let mut top = 0;
+
+        for instruction in &self.code {
+            let value = match instruction {
+                Instruction::Old => level,
+                Instruction::Const(n) => *n,
+                op => {
+                    top -= 2;
+                    apply(*op, stack[top], stack[top + 1])?
+                }
+            };
+            stack[top] = value;
+            top += 1;
         }
+
+        Some(stack[0])
     }
 }
 
 impl FromStr for Operation {
-    type Err = Infallible;
+    type Err = ParsingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split_whitespace();
-        parts.next(); // skip first "old"
-        Ok(match (parts.next(), parts.next()) {
-            (Some("*"), Some("old")) => Operation::Square,
-            (Some("*"), Some(n)) => Operation::Multiply(n.parse().unwrap()),
-            (Some("+"), Some(n)) => Operation::Add(n.parse().unwrap()),
-            _ => unreachable!(),
-        })
+        let mut compiler = Compiler {
+            tokens: tokenize(s)?.into_iter().peekable(),
+            code: vec![],
+            depth: 0,
+        };
+        compiler.expression()?;
+
+        match compiler.tokens.next() {
+            Some(token) => Err(ParsingError::new(&format!("unexpected '{}' in operation '{}'", token, s))),
+            None => Ok(Self { code: compiler.code }),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Token {
+    Old,
+    Number(usize),
+    Symbol(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Old => write!(f, "old"),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Symbol(c) => write!(f, "{}", c),
+        }
     }
 }
 
+fn tokenize(s: &str) -> Result<Vec<Token>, ParsingError> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => continue,
+            '+' | '-' | '*' | '/' | '(' | ')' => tokens.push(Token::Symbol(c)),
+            '0'..='9' => {
+                let mut end = i + 1;
+                while let Some((j, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    end = j + 1;
+                }
+                let n = s[i..end].parse().map_err(|_| ParsingError::new(&format!("invalid number '{}'", &s[i..end])))?;
+                tokens.push(Token::Number(n));
+            }
+            _ if s[i..].starts_with("old") => {
+                chars.nth(1);
+                tokens.push(Token::Old);
+            }
+            _ => return Err(ParsingError::new(&format!("unexpected '{}' in operation '{}'", c, s))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over `expression := term (('+' | '-') term)*`,
+/// `term := factor (('*' | '/') factor)*` and `factor := 'old' | number | '(' expression ')'`.
+struct Compiler<I: Iterator<Item=Token>> {
+    tokens: iter::Peekable<I>,
+    code: Vec<Instruction>,
+    depth: usize,
+}
+
+impl<I: Iterator<Item=Token>> Compiler<I> {
+    fn emit(&mut self, instruction: Instruction) -> Result<(), ParsingError> {
+        match instruction {
+            Instruction::Old | Instruction::Const(_) => self.depth += 1,
+            _ => self.depth -= 1,
+        }
+        if self.depth > MAX_STACK {
+            return Err(ParsingError::new("operation is nested too deeply"));
+        }
+        self.code.push(instruction);
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<(), ParsingError> {
+        self.term()?;
+        while let Some(Token::Symbol(c @ ('+' | '-'))) = self.tokens.peek().copied() {
+            self.tokens.next();
+            self.term()?;
+            self.emit(if c == '+' { Instruction::Add } else { Instruction::Sub })?;
+        }
+        Ok(())
+    }
+
+    fn term(&mut self) -> Result<(), ParsingError> {
+        self.factor()?;
+        while let Some(Token::Symbol(c @ ('*' | '/'))) = self.tokens.peek().copied() {
+            self.tokens.next();
+            self.factor()?;
+            if c == '/' && self.code.last() == Some(&Instruction::Const(0)) {
+                return Err(ParsingError::new("division by zero in operation"));
+            }
+            self.emit(if c == '*' { Instruction::Mul } else { Instruction::Div })?;
+        }
+        Ok(())
+    }
+
+    fn factor(&mut self) -> Result<(), ParsingError> {
+        match self.tokens.next() {
+            Some(Token::Old) => self.emit(Instruction::Old),
+            Some(Token::Number(n)) => self.emit(Instruction::Const(n)),
+            Some(Token::Symbol('(')) => {
+                self.expression()?;
+                match self.tokens.next() {
+                    Some(Token::Symbol(')')) => Ok(()),
+                    _ => Err(ParsingError::new("missing closing parenthesis in operation")),
+                }
+            }
+            Some(token) => Err(ParsingError::new(&format!("unexpected '{}' in operation", token))),
+            None => Err(ParsingError::new("unexpected end of operation")),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParsingError {
+    message: String,
+}
+
+impl ParsingError {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string()
+        }
+    }
+}
+
+impl Display for ParsingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Parsing error: {}", &self.message)
+    }
+}
+
+impl Error for ParsingError {}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
-struct Selector {
+pub struct Selector {
     divider: usize,
     on_true: usize,
     on_false: usize,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-struct Monkey {
+pub struct Monkey {
     items: Vec<usize>,
     op: Operation,
     selector: Selector,
 }
 
 impl Monkey {
-    fn predict(&self, level: usize) -> usize {
-        self.op.exec(level)
+    fn test(&self, level: usize) -> bool {
+        level % self.selector.divider == 0
     }
 
     fn select(&self, level: usize) -> usize {
-        if level % self.selector.divider == 0 {
+        if self.test(level) {
             self.selector.on_true
         } else {
             self.selector.on_false
@@ -66,83 +281,476 @@ impl Monkey {
 }
 
 impl FromStr for Monkey {
-    type Err = Infallible;
+    type Err = ParsingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut lines = s.lines().skip(1);
-
-        let items = lines.next().unwrap().split(':').nth(1).unwrap().split(',').map(|part| part.trim().parse().unwrap()).collect();
-        let op = lines.next().unwrap().split('=').nth(1).unwrap().trim().parse().unwrap();
-        let mut selector = lines.map(line_last_item);
-
-        Ok(Self {
-            items,
-            op,
-            selector: Selector {
-                divider: selector.next().unwrap(),
-                on_true: selector.next().unwrap(),
-                on_false: selector.next().unwrap(),
-            },
-        })
+        let lines: Vec<_> = numbered_lines(s).collect();
+        parse_block(&lines).map(|(_, monkey)| monkey)
     }
 }
 
-fn line_last_item<T, E>(line: &str) -> T
-    where
-        T: FromStr<Err=E>,
-        E: Debug,
-{
-    line.split_whitespace().last().unwrap().parse().unwrap()
+/// Non-blank lines, trimmed and numbered from 1, CRLF line endings included.
+fn numbered_lines(input: &str) -> impl Iterator<Item=(usize, &str)> {
+    input.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Parse a `Monkey N:` header followed by its attributes, in any order.
+fn parse_block(lines: &[(usize, &str)]) -> Result<(usize, Monkey), ParsingError> {
+    let error = |n: usize, message: &str| ParsingError::new(&format!("line {}: {}", n, message));
+
+    let (&(header_line, header), attributes) = lines.split_first()
+        .ok_or_else(|| ParsingError::new("empty monkey block"))?;
+    let index = header.strip_prefix("Monkey ")
+        .and_then(|rest| rest.strip_suffix(':'))
+        .and_then(|index| index.trim().parse().ok())
+        .ok_or_else(|| error(header_line, &format!("expected 'Monkey <index>:', got '{}'", header)))?;
+
+    let mut items = None;
+    let mut op = None;
+    let mut divider = None;
+    let mut on_true = None;
+    let mut on_false = None;
+
+    for &(n, line) in attributes {
+        let (key, value) = line.split_once(':')
+            .ok_or_else(|| error(n, &format!("expected '<attribute>: <value>', got '{}'", line)))?;
+        let value = value.trim();
+        let number = |prefix: &str| value.strip_prefix(prefix)
+            .and_then(|number| number.trim().parse::<usize>().ok())
+            .ok_or_else(|| error(n, &format!("expected '{} <number>', got '{}'", prefix, value)));
+
+        let (duplicate, name) = match key.trim() {
+            "Starting items" => {
+                let parsed = value.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| item.parse().map_err(|_| error(n, &format!("invalid worry level '{}'", item))))
+                    .collect::<Result<Vec<usize>, _>>()?;
+                (items.replace(parsed).is_some(), "starting items")
+            }
+            "Operation" => {
+                let expression = value.strip_prefix("new =")
+                    .ok_or_else(|| error(n, &format!("expected 'new = <expression>', got '{}'", value)))?;
+                let parsed = expression.trim().parse::<Operation>().map_err(|err| error(n, &err.message))?;
+                (op.replace(parsed).is_some(), "operation")
+            }
+            "Test" => (divider.replace(number("divisible by")?).is_some(), "test"),
+            "If true" => (on_true.replace(number("throw to monkey")?).is_some(), "true branch"),
+            "If false" => (on_false.replace(number("throw to monkey")?).is_some(), "false branch"),
+            key => return Err(error(n, &format!("unknown attribute '{}'", key))),
+        };
+        if duplicate {
+            return Err(error(n, &format!("duplicate {} for monkey {}", name, index)));
+        }
+    }
+
+    let missing = |name: &str| error(header_line, &format!("monkey {} has no {}", index, name));
+    let divider = divider.ok_or_else(|| missing("test"))?;
+    if divider == 0 {
+        return Err(error(header_line, &format!("monkey {} tests divisibility by 0", index)));
+    }
+
+    Ok((index, Monkey {
+        items: items.ok_or_else(|| missing("starting items"))?,
+        op: op.ok_or_else(|| missing("operation"))?,
+        selector: Selector {
+            divider,
+            on_true: on_true.ok_or_else(|| missing("true branch"))?,
+            on_false: on_false.ok_or_else(|| missing("false branch"))?,
+        },
+    }))
+}
+
+/// Parse all monkeys, whatever the order of their blocks, checking that indexes go from 0 to
+/// the number of monkeys and that every throw target exists.
+pub fn parse_monkeys(input: &str) -> Result<Vec<Monkey>, ParsingError> {
+    let lines: Vec<_> = numbered_lines(input).collect();
+    let mut starts: Vec<usize> = lines.iter()
+        .enumerate()
+        .filter(|(_, (_, line))| line.starts_with("Monkey"))
+        .map(|(i, _)| i)
+        .collect();
+    if starts.first() != Some(&0) {
+        return Err(match lines.first() {
+            Some((n, line)) => ParsingError::new(&format!("line {}: expected 'Monkey <index>:', got '{}'", n, line)),
+            None => ParsingError::new("no monkey found"),
+        });
+    }
+    starts.push(lines.len());
+
+    let mut monkeys: Vec<Option<Monkey>> = vec![None; starts.len() - 1];
+    for bounds in starts.windows(2) {
+        let (index, monkey) = parse_block(&lines[bounds[0]..bounds[1]])?;
+        let header_line = lines[bounds[0]].0;
+        match monkeys.get_mut(index) {
+            None => return Err(ParsingError::new(&format!(
+                "line {}: monkey {} out of range, there are only {} monkeys", header_line, index, monkeys.len(),
+            ))),
+            Some(Some(_)) => return Err(ParsingError::new(&format!("line {}: duplicate monkey {}", header_line, index))),
+            Some(slot) => *slot = Some(monkey),
+        }
+    }
+    let monkeys: Vec<Monkey> = monkeys.into_iter().flatten().collect();
+
+    for (i, monkey) in monkeys.iter().enumerate() {
+        for target in [monkey.selector.on_true, monkey.selector.on_false] {
+            if target >= monkeys.len() {
+                return Err(ParsingError::new(&format!("monkey {} throws to unknown monkey {}", i, target)));
+            }
+        }
+    }
+
+    Ok(monkeys)
 }
 
 #[aoc_generator(day11)]
-fn input_generator(input: &str) -> Vec<Monkey> {
-    input.split("\n\n").map(|block| block.parse().unwrap()).collect()
+pub fn input_generator(input: &str) -> Vec<Monkey> {
+    parse_monkeys(input).unwrap()
+}
+
+/// One item thrown during a monkey's turn, with the worry levels along the way.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Throw {
+    pub round: usize,
+    pub monkey: usize,
+    pub level: usize,
+    pub inspected: usize,
+    pub relieved: usize,
+    pub divisible: bool,
+    pub target: usize,
+}
+
+impl Display for Throw {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Monkey {} inspects an item with a worry level of {}, now {}, then {} after relief; {}divisible, thrown to monkey {}",
+            self.monkey,
+            self.level,
+            self.inspected,
+            self.relieved,
+            if self.divisible { "" } else { "not " },
+            self.target,
+        )
+    }
+}
+
+/// All the throws of a round and the items held by each monkey at the end of it.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Round {
+    pub round: usize,
+    pub throws: Vec<Throw>,
+    pub items: Vec<Vec<usize>>,
+}
+
+impl Display for Round {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "After round {}, the monkeys are holding items with these worry levels:", self.round)?;
+        for (i, items) in self.items.iter().enumerate() {
+            let items = items.iter().map(ToString::to_string).collect::<Vec<_>>();
+            write!(f, "\nMonkey {}: {}", i, items.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Step by step simulation, either throw by throw with `next_throw` or round by round by iterating.
+pub struct MonkeySim<F> {
+    monkeys: Vec<Monkey>,
+    inibitor: F,
+    counters: Vec<usize>,
+    round: usize,
+    turn: usize,
+    holding: Vec<usize>,
+    thrown: usize,
+}
+
+impl<F> MonkeySim<F>
+    where
+        F: Fn(usize) -> usize
+{
+    pub fn new(monkeys: &[Monkey], inibitor: F) -> Self {
+        Self {
+            monkeys: monkeys.to_vec(),
+            inibitor,
+            counters: vec![0; monkeys.len()],
+            round: 1,
+            turn: 0,
+            holding: vec![],
+            thrown: 0,
+        }
+    }
+
+    /// Next throw of the current round, or `None` once the round is over, the following call
+    /// starting the next round.
+    ///
+    /// # Panics
+    ///
+    /// When a worry level gets out of range, see `try_next_throw`.
+    pub fn next_throw(&mut self) -> Option<Throw> {
+        self.try_next_throw().unwrap_or_else(|overflow| panic!("{}", overflow))
+    }
+
+    /// Same as `next_throw`, but stops on arithmetic overflow or division by zero, leaving the item
+    /// to the monkey.
+    pub fn try_next_throw(&mut self) -> Result<Option<Throw>, Overflow> {
+        while self.holding.is_empty() {
+            if self.turn == self.monkeys.len() {
+                self.turn = 0;
+                self.round += 1;
+                return Ok(None);
+            }
+            mem::swap(&mut self.holding, &mut self.monkeys[self.turn].items);
+            self.holding.reverse();
+            self.turn += 1;
+            self.thrown = 0;
+        }
+
+        let i = self.turn - 1;
+        let level = *self.holding.last().unwrap();
+        let inspected = self.monkeys[i].op.exec_checked(level)
+            .ok_or(Overflow { round: self.round, monkey: i, item: self.thrown, level })?;
+        self.holding.pop();
+        self.thrown += 1;
+        self.counters[i] += 1;
+
+        let relieved = (self.inibitor)(inspected);
+        let divisible = self.monkeys[i].test(relieved);
+        let target = self.monkeys[i].select(relieved);
+        self.monkeys[target].items.push(relieved);
+
+        Ok(Some(Throw {
+            round: self.round,
+            monkey: i,
+            level,
+            inspected,
+            relieved,
+            divisible,
+            target,
+        }))
+    }
+
+    /// Items held by each monkey, including the ones not yet thrown by the monkey whose turn it is.
+    pub fn items(&self) -> Vec<Vec<usize>> {
+        let mut items: Vec<_> = self.monkeys.iter().map(|monkey| monkey.items.clone()).collect();
+        if let Some(i) = self.turn.checked_sub(1) {
+            items[i].splice(0..0, self.holding.iter().rev().copied());
+        }
+        items
+    }
+
+    /// How many items each monkey inspected so far.
+    pub fn counters(&self) -> &[usize] {
+        &self.counters
+    }
 }
 
-fn solve<F>(monkeys: &[Monkey], rounds: usize, inibitor: F) -> usize
+/// Whole rounds, one after the other.
+///
+/// # Panics
+///
+/// When a worry level gets out of range, like `next_throw`. Use `try_next_throw` to stop on
+/// overflow instead.
+impl<F> Iterator for MonkeySim<F>
     where
         F: Fn(usize) -> usize
 {
-    let mut monkeys = monkeys.to_vec();
+    type Item = Round;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let round = self.round;
+        let throws = iter::from_fn(|| self.next_throw()).collect();
+        Some(Round { round, throws, items: self.items() })
+    }
+}
+
+/// Where a worry level got out of range: the round, the monkey inspecting, the index of the item in
+/// the list the monkey held at the start of its turn and the item's worry level.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Overflow {
+    pub round: usize,
+    pub monkey: usize,
+    pub item: usize,
+    pub level: usize,
+}
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Overflow: round {}, monkey {} inspecting item {} with a worry level of {}",
+            self.round, self.monkey, self.item, self.level,
+        )
+    }
+}
+
+impl Error for Overflow {}
+
+/// How worry levels are kept manageable after each inspection.
+pub enum Relief {
+    /// Integer division, part1 divides by 3.
+    Divide(usize),
+    /// Remainder modulo the least common multiple of the dividers, which preserves every test as
+    /// long as operations only add and multiply (see `Operation::is_modular`). With `-` or `/`,
+    /// inspections may differ from the unreduced simulation.
+    ModLcm,
+    Custom(Box<dyn Fn(usize) -> usize>),
+}
+
+pub struct SimConfig {
+    pub rounds: usize,
+    pub relief: Relief,
+}
+
+/// Run the simulation and return how many items each monkey inspected.
+pub fn simulate(monkeys: &[Monkey], config: &SimConfig) -> Result<Vec<usize>, Overflow> {
+    let module = lcm(monkeys);
+    let relief = |level: usize| match &config.relief {
+        Relief::Divide(n) => level / n,
+        Relief::ModLcm => level % module,
+        Relief::Custom(f) => f(level),
+    };
+
+    let mut sim = MonkeySim::new(monkeys, relief);
+    for _ in 0..config.rounds {
+        while sim.try_next_throw()?.is_some() {}
+    }
+    Ok(sim.counters().to_vec())
+}
+
+/// Product of the `k` highest counters, the "monkey business" being `top_product(counters, 2)`.
+pub fn top_product<T>(counters: &[T], k: usize) -> T
+    where
+        T: Copy + Ord + Product<T>,
+{
+    let mut counters = counters.to_vec();
+    counters.sort_unstable_by(|a, b| b.cmp(a));
+    counters.into_iter().take(k).product()
+}
+
+fn lcm(monkeys: &[Monkey]) -> usize {
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+
+    monkeys.iter()
+        .map(|monkey| monkey.selector.divider)
+        .fold(1, |acc, divider| acc / gcd(acc, divider) * divider)
+}
+
+/// Arbitrary-precision simulation dividing worry levels by `relief` after each inspection,
+/// returning how many items each monkey inspected.
+pub fn inspections_exact(monkeys: &[Monkey], rounds: usize, relief: usize) -> Vec<usize> {
+    let relief = BigUint::from(relief);
+    let mut items: Vec<Vec<BigUint>> = monkeys.iter()
+        .map(|monkey| monkey.items.iter().map(|item| BigUint::from(*item)).collect())
+        .collect();
     let mut counters = vec![0; monkeys.len()];
 
     for _ in 0..rounds {
-        for i in 0..monkeys.len() {
-            let mut items = vec![];
-            mem::swap(&mut items, &mut monkeys[i].items);
-            for item in items {
+        for (i, monkey) in monkeys.iter().enumerate() {
+            for level in mem::take(&mut items[i]) {
                 counters[i] += 1;
-                let (j, item) = {
-                    let level = inibitor(monkeys[i].predict(item));
-                    let next_monkey = monkeys[i].select(level);
-                    (next_monkey, level)
+                let level = monkey.op.exec_big(&level) / &relief;
+                let target = if (&level % monkey.selector.divider).is_zero() {
+                    monkey.selector.on_true
+                } else {
+                    monkey.selector.on_false
                 };
-                monkeys[j].items.push(item);
+                items[target].push(level);
             }
         }
     }
 
-    counters.select_nth_unstable_by(2, |a, b| b.cmp(a));
-    counters[0] * counters[1]
+    counters
+}
+
+/// Inspections per monkey after `rounds` rounds of part2 rules (worry levels reduced modulo the
+/// least common multiple of dividers), following each item on its own: an item's state at the start of a round
+/// is its monkey and worry level, so it eventually cycles and the remaining rounds can be
+/// extrapolated. `None` when an operation isn't compatible with modular reduction, see
+/// `Operation::is_modular`, or when a worry level overflows, where `simulate` reports an `Overflow`.
+pub fn inspections_fast_forward(monkeys: &[Monkey], rounds: u64) -> Option<Vec<u64>> {
+    if !monkeys.iter().all(|monkey| monkey.op.is_modular()) {
+        return None;
+    }
+
+    let module = lcm(monkeys);
+    let mut counters = vec![0; monkeys.len()];
+
+    for (start, monkey) in monkeys.iter().enumerate() {
+        for item in &monkey.items {
+            let item_counters = item_inspections(monkeys, module, (start, item % module), rounds)?;
+            for (counter, count) in counters.iter_mut().zip(item_counters) {
+                *counter += count;
+            }
+        }
+    }
+
+    Some(counters)
+}
+
+fn item_inspections(monkeys: &[Monkey], module: usize, (mut monkey, mut level): (usize, usize), rounds: u64) -> Option<Vec<u64>> {
+    let mut seen = HashMap::new();
+    let mut history = vec![vec![0; monkeys.len()]];
+    let mut counters = vec![0; monkeys.len()];
+
+    for round in 0..rounds {
+        if let Some(&start) = seen.get(&(monkey, level)) {
+            let length = round - start;
+            let (cycles, rest) = ((rounds - round) / length, ((rounds - round) % length) as usize);
+            let start = start as usize;
+            for (i, counter) in counters.iter_mut().enumerate() {
+                let cycle = history[history.len() - 1][i] - history[start][i];
+                *counter += cycles * cycle + history[start + rest][i] - history[start][i];
+            }
+            return Some(counters);
+        }
+        seen.insert((monkey, level), round);
+
+        loop {
+            counters[monkey] += 1;
+            level = monkeys[monkey].op.exec_checked(level)? % module;
+            let target = monkeys[monkey].select(level);
+            let next_round = target <= monkey;
+            monkey = target;
+            if next_round {
+                break;
+            }
+        }
+        history.push(counters.clone());
+    }
+
+    Some(counters)
 }
 
 #[aoc(day11, part1)]
 fn solve_part1(monkeys: &[Monkey]) -> usize {
-    let inibitor = |x: usize| x / 3;
-    solve(monkeys, 20, inibitor)
+    let config = SimConfig { rounds: 20, relief: Relief::Divide(3) };
+    let counters = simulate(monkeys, &config)
+        .unwrap_or_else(|_| inspections_exact(monkeys, config.rounds, 3));
+    top_product(&counters, 2)
 }
 
 #[aoc(day11, part2)]
 fn solve_part2(monkeys: &[Monkey]) -> usize {
-    let module: usize = monkeys.iter().map(|monkey| monkey.selector.divider).product();
-    let inibitor = |x: usize| x % module;
-    solve(monkeys, 10_000, inibitor)
+    let config = SimConfig { rounds: 10_000, relief: Relief::ModLcm };
+    top_product(&simulate(monkeys, &config).unwrap(), 2)
+}
+
+#[aoc(day11, part2, cycles)]
+fn solve_part2_cycles(monkeys: &[Monkey]) -> u64 {
+    let counters = inspections_fast_forward(monkeys, 10_000)
+        .expect("operations must only add and multiply, without overflowing");
+    top_product(&counters, 2)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{input_generator, Monkey, Operation, Selector, solve_part1, solve_part2};
+    use super::{input_generator, inspections_exact, parse_monkeys, inspections_fast_forward, Monkey, MonkeySim, Operation, Relief, Selector, SimConfig, simulate, solve_part1, solve_part2, solve_part2_cycles, top_product};
 
     static INPUT: &str = "\
 Monkey 0:
@@ -178,7 +786,7 @@ Monkey 3:
         let expected = vec![
             Monkey {
                 items: vec![79, 98],
-                op: Operation::Multiply(19),
+                op: "old * 19".parse().unwrap(),
                 selector: Selector {
                     divider: 23,
                     on_true: 2,
@@ -187,7 +795,7 @@ Monkey 3:
             },
             Monkey {
                 items: vec![54, 65, 75, 74],
-                op: Operation::Add(6),
+                op: "old + 6".parse().unwrap(),
                 selector: Selector {
                     divider: 19,
                     on_true: 2,
@@ -196,7 +804,7 @@ Monkey 3:
             },
             Monkey {
                 items: vec![79, 60, 97],
-                op: Operation::Square,
+                op: "old * old".parse().unwrap(),
                 selector: Selector {
                     divider: 13,
                     on_true: 1,
@@ -205,7 +813,7 @@ Monkey 3:
             },
             Monkey {
                 items: vec![74],
-                op: Operation::Add(3),
+                op: "old + 3".parse().unwrap(),
                 selector: Selector {
                     divider: 17,
                     on_true: 0,
@@ -225,4 +833,251 @@ Monkey 3:
     fn solver_part2_match_example() {
         assert_eq!(solve_part2(&input_generator(INPUT)), 2713310158);
     }
+
+    #[test]
+    fn operations_evaluate_expressions() {
+        let exec = |op: &str, old| op.parse::<Operation>().unwrap().exec(old);
+        assert_eq!(exec("old * old", 7), 49);
+        assert_eq!(exec("old + old", 7), 14);
+        assert_eq!(exec("3 * old", 7), 21);
+        assert_eq!(exec("old - 2 * 3", 7), 1);
+        assert_eq!(exec("(old - 2) * 3", 7), 15);
+        assert_eq!(exec("100 / old / 2", 7), 7);
+        assert_eq!(exec("((old))", 7), 7);
+        assert_eq!(exec("42", 7), 42);
+    }
+
+    #[test]
+    fn operations_saturate_subtraction() {
+        let op = "old - 10".parse::<Operation>().unwrap();
+        assert_eq!(op.exec(3), 0);
+        assert_eq!(op.exec_checked(3), Some(0));
+        assert_eq!(op.exec_big(&3u32.into()), 0u32.into());
+        assert_eq!(op.exec(13), 3);
+        assert!(!op.is_modular());
+        assert!("old * old + 3".parse::<Operation>().unwrap().is_modular());
+    }
+
+    #[test]
+    fn operations_reject_division_by_zero() {
+        assert!("old / 0".parse::<Operation>().is_err());
+        assert!("old / (0)".parse::<Operation>().is_err());
+        assert_eq!("old / (old - old)".parse::<Operation>().unwrap().exec_checked(5), None);
+        assert_eq!("old / 10".parse::<Operation>().unwrap().exec(25), 2);
+    }
+
+    #[test]
+    fn sim_follows_subtracting_operations() {
+        let input = INPUT.replace("old * 19", "old - 100");
+        let mut sim = MonkeySim::new(&input_generator(&input), |x| x / 3);
+        let first = sim.next_throw().unwrap();
+        assert_eq!((first.level, first.inspected, first.relieved), (79, 0, 0));
+    }
+
+    #[test]
+    fn operations_reject_invalid_expressions() {
+        assert!("old *".parse::<Operation>().is_err());
+        assert!("(old + 1".parse::<Operation>().is_err());
+        assert!("old + 1)".parse::<Operation>().is_err());
+        assert!("old ^ 2".parse::<Operation>().is_err());
+        assert!("old old".parse::<Operation>().is_err());
+        assert!("".parse::<Operation>().is_err());
+    }
+
+    #[test]
+    fn sim_reports_throws_of_first_turn() {
+        let mut sim = MonkeySim::new(&input_generator(INPUT), |x| x / 3);
+        let first = sim.next_throw().unwrap();
+        assert_eq!(first.to_string(), "Monkey 0 inspects an item with a worry level of 79, now 1501, then 500 after relief; not divisible, thrown to monkey 3");
+        let second = sim.next_throw().unwrap();
+        assert_eq!((second.level, second.inspected, second.relieved, second.target), (98, 1862, 620, 3));
+        assert_eq!(sim.items(), vec![vec![], vec![54, 65, 75, 74], vec![79, 60, 97], vec![74, 500, 620]]);
+    }
+
+    #[test]
+    fn sim_snapshots_rounds() {
+        let mut sim = MonkeySim::new(&input_generator(INPUT), |x| x / 3);
+        let first = sim.next().unwrap();
+        assert_eq!(first.throws.len(), 14);
+        let expected = "\
+After round 1, the monkeys are holding items with these worry levels:
+Monkey 0: 20, 23, 27, 26
+Monkey 1: 2080, 25, 167, 207, 401, 1046
+Monkey 2: 
+Monkey 3: ";
+        assert_eq!(first.to_string(), expected);
+        let last = sim.nth(18).unwrap();
+        assert_eq!(last.round, 20);
+        assert_eq!(last.items[0], vec![10, 12, 14, 26, 34]);
+        assert_eq!(sim.counters(), [101, 95, 7, 105]);
+    }
+
+    #[test]
+    fn checked_sim_reports_overflow() {
+        let monkeys = input_generator(INPUT);
+        let mut sim = MonkeySim::new(&monkeys, |x| x);
+        let mut throws = vec![];
+        let overflow = loop {
+            match sim.try_next_throw() {
+                Ok(throw) => throws.extend(throw),
+                Err(overflow) => break overflow,
+            }
+        };
+        let thrown = throws.iter()
+            .filter(|throw| (throw.round, throw.monkey) == (overflow.round, overflow.monkey))
+            .count();
+        assert_eq!(overflow.item, thrown);
+        assert_eq!(monkeys[overflow.monkey].op.exec_checked(overflow.level), None);
+        assert_eq!(sim.items()[overflow.monkey][0], overflow.level);
+        assert_eq!(sim.try_next_throw(), Err(overflow));
+    }
+
+    #[test]
+    fn exact_inspections_match_checked_sim() {
+        let monkeys = input_generator(INPUT);
+        assert_eq!(inspections_exact(&monkeys, 20, 3), vec![101, 95, 7, 105]);
+
+        let mut sim = MonkeySim::new(&monkeys, |x| x / 3);
+        let mut complete = sim.counters().to_vec();
+        let rounds = (1..).find(|_| {
+            loop {
+                match sim.try_next_throw() {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => {
+                        complete = sim.counters().to_vec();
+                        break false;
+                    }
+                    Err(_) => break true,
+                }
+            }
+        }).unwrap();
+        assert!(rounds > 20);
+        assert_eq!(inspections_exact(&monkeys, rounds - 1, 3), complete);
+    }
+
+    #[test]
+    fn solver_part2_cycles_match_example() {
+        assert_eq!(solve_part2_cycles(&input_generator(INPUT)), 2713310158);
+    }
+
+    #[test]
+    fn fast_forward_matches_sim() {
+        let monkeys = input_generator(INPUT);
+        let module = monkeys.iter().map(|monkey| monkey.selector.divider).product::<usize>();
+        let mut sim = MonkeySim::new(&monkeys, |x| x % module);
+        let mut played = 0;
+        for rounds in [1, 20, 1000, 2345] {
+            sim.by_ref().take(rounds - played).for_each(drop);
+            played = rounds;
+            let counters: Vec<u64> = sim.counters().iter().map(|c| *c as u64).collect();
+            assert_eq!(inspections_fast_forward(&monkeys, rounds as u64), Some(counters));
+        }
+    }
+
+    #[test]
+    fn fast_forward_handles_huge_round_counts() {
+        // The item goes to monkey 1 on even rounds and to monkey 2 on odd ones.
+        let monkeys = parse_monkeys("\
+Monkey 0:
+  Starting items: 0
+  Operation: new = old + 1
+  Test: divisible by 2
+    If true: throw to monkey 1
+    If false: throw to monkey 2
+
+Monkey 1:
+  Starting items:
+  Operation: new = old
+  Test: divisible by 1
+    If true: throw to monkey 0
+    If false: throw to monkey 0
+
+Monkey 2:
+  Starting items:
+  Operation: new = old
+  Test: divisible by 1
+    If true: throw to monkey 0
+    If false: throw to monkey 0").unwrap();
+        let counters = simulate(&monkeys, &SimConfig { rounds: 7, relief: Relief::Custom(Box::new(|x| x)) }).unwrap();
+        assert_eq!(counters, vec![7, 3, 4]);
+        assert_eq!(inspections_fast_forward(&monkeys, 7), Some(vec![7, 3, 4]));
+
+        let rounds = 1_000_000_000_001;
+        assert_eq!(inspections_fast_forward(&monkeys, rounds), Some(vec![rounds, rounds / 2, rounds / 2 + 1]));
+        assert_eq!(
+            inspections_fast_forward(&input_generator(INPUT), 10_000),
+            Some(vec![52166, 47830, 1938, 52013]),
+        );
+    }
+
+    #[test]
+    fn fast_forward_reports_overflow() {
+        let monkeys = parse_monkeys("\
+Monkey 0:
+  Starting items: 4294967300
+  Operation: new = old * old
+  Test: divisible by 4294967311
+    If true: throw to monkey 0
+    If false: throw to monkey 0").unwrap();
+        assert!(simulate(&monkeys, &SimConfig { rounds: 1, relief: Relief::ModLcm }).is_err());
+        assert_eq!(inspections_fast_forward(&monkeys, 1), None);
+    }
+
+    #[test]
+    fn fast_forward_rejects_non_modular_operations() {
+        let input = INPUT.replace("old * 19", "old / 2");
+        assert_eq!(inspections_fast_forward(&input_generator(&input), 6), None);
+        let input = INPUT.replace("old + 6", "old - 1");
+        assert_eq!(inspections_fast_forward(&input_generator(&input), 6), None);
+    }
+
+    #[test]
+    fn simulate_returns_all_counters() {
+        let monkeys = input_generator(INPUT);
+        let counters = simulate(&monkeys, &SimConfig { rounds: 20, relief: Relief::Divide(3) }).unwrap();
+        assert_eq!(counters, vec![101, 95, 7, 105]);
+        let counters = simulate(&monkeys, &SimConfig { rounds: 10_000, relief: Relief::ModLcm }).unwrap();
+        assert_eq!(counters, vec![52166, 47830, 1938, 52013]);
+        let counters = simulate(&monkeys, &SimConfig { rounds: 1, relief: Relief::Custom(Box::new(|x| x % 2)) }).unwrap();
+        assert_eq!(counters, vec![2, 4, 6, 4]);
+    }
+
+    #[test]
+    fn top_product_multiplies_highest_counters() {
+        assert_eq!(top_product(&[101, 95, 7, 105], 2), 10605);
+        assert_eq!(top_product(&[101, 95, 7, 105], 3), 1007475);
+        assert_eq!(top_product(&[101, 95, 7, 105], 10), 7052325);
+        assert_eq!(top_product::<usize>(&[], 2), 1);
+    }
+
+    #[test]
+    fn parser_accepts_shuffled_blocks_and_crlf() {
+        let mut blocks: Vec<&str> = INPUT.split("\n\n").collect();
+        blocks.reverse();
+        let shuffled = format!("\n\n{}\n\n\n", blocks.join("\n\n\n")).replace('\n', "\r\n");
+        assert_eq!(parse_monkeys(&shuffled), Ok(input_generator(INPUT)));
+    }
+
+    #[test]
+    fn parser_reports_descriptive_errors() {
+        let error = |input: &str| parse_monkeys(input).unwrap_err().to_string();
+        let block = |index: usize, on_true: usize| format!("\
+Monkey {}:
+  Starting items: 79, 98
+  Operation: new = old * 19
+  Test: divisible by 23
+    If true: throw to monkey {}
+    If false: throw to monkey 0", index, on_true);
+
+        assert_eq!(error(&block(1, 0)), "Parsing error: line 1: monkey 1 out of range, there are only 1 monkeys");
+        assert_eq!(error(&format!("{}\n\n{}", block(0, 1), block(0, 0))), "Parsing error: line 8: duplicate monkey 0");
+        assert_eq!(error(&block(0, 4)), "Parsing error: monkey 0 throws to unknown monkey 4");
+        assert_eq!(error(&block(0, 0).replace("Monkey 0:", "Monkey zero:")), "Parsing error: line 1: expected 'Monkey <index>:', got 'Monkey zero:'");
+        assert_eq!(error(&block(0, 0).replace("98", "x")), "Parsing error: line 2: invalid worry level 'x'");
+        assert_eq!(error(&block(0, 0).replace("old * 19", "old ^ 19")), "Parsing error: line 3: unexpected '^' in operation 'old ^ 19'");
+        assert_eq!(error(&block(0, 0).replace("divisible by", "multiple of")), "Parsing error: line 4: expected 'divisible by <number>', got 'multiple of 23'");
+        assert_eq!(error(&block(0, 0).replace("  Test: divisible by 23\n", "")), "Parsing error: line 1: monkey 0 has no test");
+        assert_eq!(error(&block(0, 0).replace("If false", "If maybe")), "Parsing error: line 6: unknown attribute 'If maybe'");
+        assert_eq!(error(""), "Parsing error: no monkey found");
+    }
 }
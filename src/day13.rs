@@ -1,41 +1,27 @@
 use std::cmp::Ordering;
-use std::cmp::Ordering::{Equal, Greater, Less};
-use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::slice;
 use std::str::FromStr;
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use serde_json::Value;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-enum Packet {
+pub enum Packet {
     Int(usize),
     List(Vec<Packet>),
 }
 
+/// An integer compared against a list behaves as a one-element list, borrowed in place with
+/// `slice::from_ref` rather than wrapped in a new `Packet::List`.
 impl Ord for Packet {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (xs@Packet::List(_), y@Packet::Int(_)) => xs.cmp(&Packet::List(vec![y.clone()])),
-            (x@Packet::Int(_), ys@Packet::List(_)) => Packet::List(vec![x.clone()]).cmp(ys),
             (Packet::Int(x), Packet::Int(y)) => x.cmp(y),
-            (Packet::List(xs), Packet::List(ys)) => {
-                let mut xs = xs.iter();
-                let mut ys = ys.iter();
-                loop {
-                    match (xs.next(), ys.next()) {
-                        (None, None) => break Equal,
-                        (Some(_), None) => break Greater,
-                        (None, Some(_)) => break Less,
-                        (Some(x), Some(y)) => {
-                            match x.cmp(y) {
-                                Equal => continue,
-                                ord => {
-                                    break ord;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            (Packet::List(xs), Packet::List(ys)) => xs.iter().cmp(ys.iter()),
+            (Packet::List(xs), y @ Packet::Int(_)) => xs.iter().cmp(slice::from_ref(y).iter()),
+            (x @ Packet::Int(_), Packet::List(ys)) => slice::from_ref(x).iter().cmp(ys.iter()),
         }
     }
 }
@@ -46,42 +32,297 @@ impl PartialOrd for Packet {
     }
 }
 
+impl Packet {
+    fn write_pretty(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        match self {
+            Packet::Int(n) => write!(f, "{}", n),
+            Packet::List(xs) if xs.is_empty() => write!(f, "[]"),
+            Packet::List(xs) => {
+                writeln!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    write!(f, "{:indent$}", "", indent = 2 * (depth + 1))?;
+                    x.write_pretty(f, depth + 1)?;
+                    if i + 1 < xs.len() {
+                        write!(f, ",")?;
+                    }
+                    writeln!(f)?;
+                }
+                write!(f, "{:indent$}]", "", indent = 2 * depth)
+            }
+        }
+    }
+}
+
+/// Compact form by default (`[1,[2,3]]`), one element per line with two-space indentation with
+/// the alternate flag (`{:#}`).
+impl Display for Packet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return self.write_pretty(f, 0);
+        }
+
+        match self {
+            Packet::Int(n) => write!(f, "{}", n),
+            Packet::List(xs) => {
+                write!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
 impl FromStr for Packet {
-    type Err = Infallible;
+    type Err = ParsingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut stream = s.chars().peekable();
-        let mut stack: Vec<Vec<Packet>> = vec![];
-        let mut num_buffer = String::new();
+        let mut parser = Parser { input: s, offset: 0 };
+        let packet = parser.packet()?;
+        parser.skip_whitespaces();
+        match parser.peek() {
+            None => Ok(packet),
+            Some(c) => Err(parser.error(ErrorReason::UnexpectedChar(c))),
+        }
+    }
+}
+
+/// Recursive descent parser keeping track of the byte offset in the input.
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl Parser<'_> {
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn skip_whitespaces(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.offset += c.len_utf8();
+        }
+    }
+
+    fn error(&self, reason: ErrorReason) -> ParsingError {
+        ParsingError { offset: self.offset, reason }
+    }
+
+    fn packet(&mut self) -> Result<Packet, ParsingError> {
+        self.skip_whitespaces();
+        match self.peek() {
+            Some('[') => self.list(),
+            Some(c) if c.is_ascii_digit() => self.int(),
+            Some(c) => Err(self.error(ErrorReason::UnexpectedChar(c))),
+            None => Err(self.error(ErrorReason::UnexpectedEnd)),
+        }
+    }
+
+    fn int(&mut self) -> Result<Packet, ParsingError> {
+        let start = self.offset;
+        let digits = self.input[start..].bytes().take_while(u8::is_ascii_digit).count();
+        self.offset += digits;
+        self.input[start..self.offset].parse()
+            .map(Packet::Int)
+            .map_err(|_| ParsingError { offset: start, reason: ErrorReason::IntegerOverflow })
+    }
+
+    fn list(&mut self) -> Result<Packet, ParsingError> {
+        self.offset += 1; // skip '['
+        let mut items = vec![];
+
+        self.skip_whitespaces();
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(Packet::List(items));
+        }
 
         loop {
-            while char::is_numeric(*stream.peek().unwrap()) {
-                num_buffer.push(stream.next().unwrap());
+            items.push(self.packet()?);
+            self.skip_whitespaces();
+            match self.peek() {
+                Some(',') => self.offset += 1,
+                Some(']') => {
+                    self.offset += 1;
+                    return Ok(Packet::List(items));
+                }
+                Some(c) => return Err(self.error(ErrorReason::UnexpectedChar(c))),
+                None => return Err(self.error(ErrorReason::UnexpectedEnd)),
             }
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ErrorReason {
+    UnexpectedChar(char),
+    UnexpectedEnd,
+    IntegerOverflow,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParsingError {
+    pub offset: usize,
+    pub reason: ErrorReason,
+}
+
+impl Display for ParsingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.reason {
+            ErrorReason::UnexpectedChar(c) => write!(f, "Parsing error: unexpected char '{}' at offset {}", c, self.offset),
+            ErrorReason::UnexpectedEnd => write!(f, "Parsing error: unexpected end of input at offset {}", self.offset),
+            ErrorReason::IntegerOverflow => write!(f, "Parsing error: integer too large at offset {}", self.offset),
+        }
+    }
+}
+
+impl Error for ParsingError {}
+
+impl From<&Packet> for Value {
+    fn from(packet: &Packet) -> Self {
+        match packet {
+            Packet::Int(n) => Value::from(*n),
+            Packet::List(xs) => Value::Array(xs.iter().map(Value::from).collect()),
+        }
+    }
+}
+
+/// Only non-negative integers and arrays of those map to packets.
+impl TryFrom<&Value> for Packet {
+    type Error = JsonError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => n.as_u64()
+                .and_then(|n| usize::try_from(n).ok())
+                .map(Packet::Int)
+                .ok_or_else(|| JsonError { value: value.clone() }),
+            Value::Array(xs) => xs.iter().map(Packet::try_from).collect::<Result<_, _>>().map(Packet::List),
+            _ => Err(JsonError { value: value.clone() }),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct JsonError {
+    pub value: Value,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JSON value {} is not a packet", self.value)
+    }
+}
+
+impl Error for JsonError {}
+
+/// One line of the comparison walkthrough from the puzzle statement, `depth` being its nesting.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Step {
+    pub depth: usize,
+    pub kind: StepKind,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum StepKind {
+    Compare(Packet, Packet),
+    ConvertLeft(Packet),
+    ConvertRight(Packet),
+    LeftSmaller,
+    RightSmaller,
+    LeftRanOut,
+    RightRanOut,
+}
 
-            if !num_buffer.is_empty() {
-                stack.last_mut().unwrap().push(Packet::Int(num_buffer.parse().unwrap()));
-                num_buffer.clear();
-            } else {
-                match stream.next() {
-                    None => break,
-                    Some(',') => continue,
-                    Some('[') => {
-                        stack.push(vec![]);
+impl Display for StepKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepKind::Compare(left, right) => write!(f, "Compare {} vs {}", left, right),
+            StepKind::ConvertLeft(left) => write!(f, "Mixed types; convert left to {} and retry comparison", left),
+            StepKind::ConvertRight(right) => write!(f, "Mixed types; convert right to {} and retry comparison", right),
+            StepKind::LeftSmaller => write!(f, "Left side is smaller, so inputs are in the right order"),
+            StepKind::RightSmaller => write!(f, "Right side is smaller, so inputs are not in the right order"),
+            StepKind::LeftRanOut => write!(f, "Left side ran out of items, so inputs are in the right order"),
+            StepKind::RightRanOut => write!(f, "Right side ran out of items, so inputs are not in the right order"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Explanation {
+    pub ordering: Ordering,
+    pub steps: Vec<Step>,
+}
+
+/// Renders the steps as the nested bullet list of the puzzle statement.
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:indent$}- {}", "", step.kind, indent = 2 * step.depth)?;
+        }
+        Ok(())
+    }
+}
+
+/// Same result as `left.cmp(right)`, along with the trace of how it was reached.
+pub fn explain_cmp(left: &Packet, right: &Packet) -> Explanation {
+    let mut steps = vec![];
+    let ordering = explain(left, right, 0, &mut steps);
+    Explanation { ordering, steps }
+}
+
+fn explain(left: &Packet, right: &Packet, depth: usize, steps: &mut Vec<Step>) -> Ordering {
+    steps.push(Step { depth, kind: StepKind::Compare(left.clone(), right.clone()) });
+    let step = |kind| Step { depth: depth + 1, kind };
+
+    match (left, right) {
+        (Packet::Int(x), Packet::Int(y)) => {
+            let ordering = x.cmp(y);
+            match ordering {
+                Ordering::Less => steps.push(step(StepKind::LeftSmaller)),
+                Ordering::Greater => steps.push(step(StepKind::RightSmaller)),
+                Ordering::Equal => {}
+            }
+            ordering
+        }
+        (Packet::Int(_), Packet::List(_)) => {
+            let converted = Packet::List(vec![left.clone()]);
+            steps.push(step(StepKind::ConvertLeft(converted.clone())));
+            explain(&converted, right, depth + 1, steps)
+        }
+        (Packet::List(_), Packet::Int(_)) => {
+            let converted = Packet::List(vec![right.clone()]);
+            steps.push(step(StepKind::ConvertRight(converted.clone())));
+            explain(left, &converted, depth + 1, steps)
+        }
+        (Packet::List(xs), Packet::List(ys)) => {
+            for i in 0.. {
+                let ordering = match (xs.get(i), ys.get(i)) {
+                    (None, None) => Ordering::Equal,
+                    (None, Some(_)) => {
+                        steps.push(step(StepKind::LeftRanOut));
+                        Ordering::Less
                     }
-                    Some(']') => {
-                        let sublist = stack.pop().unwrap();
-                        if let Some(list) = stack.last_mut() {
-                            list.push(Packet::List(sublist));
-                        } else {
-                            return Ok(Packet::List(sublist));
-                        }
+                    (Some(_), None) => {
+                        steps.push(step(StepKind::RightRanOut));
+                        Ordering::Greater
                     }
-                    _ => unreachable!(),
+                    (Some(x), Some(y)) => explain(x, y, depth + 1, steps),
+                };
+                if ordering != Ordering::Equal || i >= xs.len() {
+                    return ordering;
                 }
             }
+            unreachable!()
         }
-        unreachable!()
     }
 }
 
@@ -99,7 +340,7 @@ fn input_generator1(input: &str) -> Vec<(Packet, Packet)> {
 fn input_generator2(input: &str) -> Vec<Packet> {
     input.lines()
         .filter(|line| !line.is_empty())
-        .flat_map(FromStr::from_str)
+        .map(|line| line.parse().unwrap())
         .collect()
 }
 
@@ -133,9 +374,36 @@ fn solve_part2(packets: &[Packet]) -> usize {
     i2 * i6
 }
 
+/// 1-based position `divider` would get once inserted into `packets` and sorted.
+pub fn rank_of(divider: &Packet, packets: &[Packet]) -> usize {
+    1 + packets.iter().filter(|packet| *packet < divider).count()
+}
+
+/// 1-based positions of all `dividers` once inserted together into `packets` and sorted, in a
+/// single pass over `packets`.
+pub fn ranks_of(dividers: &[Packet], packets: &[Packet]) -> Vec<usize> {
+    let mut ranks: Vec<usize> = dividers.iter().map(|divider| rank_of(divider, dividers)).collect();
+    for packet in packets {
+        for (rank, divider) in ranks.iter_mut().zip(dividers) {
+            if packet < divider {
+                *rank += 1;
+            }
+        }
+    }
+    ranks
+}
+
+#[aoc(day13, part2, counting)]
+fn solve_part2_counting(packets: &[Packet]) -> usize {
+    let dividers = ["[[2]]", "[[6]]"].map(|divider| divider.parse().unwrap());
+    ranks_of(&dividers, packets).iter().product()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{input_generator1, input_generator2, solve_part1, solve_part2, Packet};
+    use serde_json::{json, Value};
+
+    use super::{ErrorReason, explain_cmp, input_generator1, input_generator2, JsonError, Packet, ParsingError, rank_of, ranks_of, solve_part1, solve_part2, solve_part2_counting};
 
     static INPUT: &str = "\
 [1,1,3,1,1]
@@ -315,4 +583,156 @@ mod tests {
         assert_eq!(solve_part2(&input_generator2(INPUT)), 140);
     }
 
+    #[test]
+    fn solver_part2_counting_match_example() {
+        assert_eq!(solve_part2_counting(&input_generator2(INPUT)), 140);
+    }
+
+    #[test]
+    fn ranks_match_sorted_positions() {
+        let packets = input_generator2(INPUT);
+        let dividers: Vec<Packet> = ["[[2]]", "[[6]]", "[5]", "[[1],[3]]"].iter().map(|d| d.parse().unwrap()).collect();
+        let mut sorted = packets.iter().chain(&dividers).cloned().collect::<Vec<_>>();
+        sorted.sort();
+        let expected: Vec<usize> = dividers.iter().map(|d| sorted.binary_search(d).unwrap() + 1).collect();
+        assert_eq!(ranks_of(&dividers, &packets), expected);
+        assert_eq!(rank_of(&dividers[0], &packets), 10);
+    }
+
+    #[test]
+    fn display_writes_compact_form() {
+        for line in INPUT.lines().filter(|line| !line.is_empty()) {
+            assert_eq!(line.parse::<Packet>().unwrap().to_string(), line);
+        }
+    }
+
+    #[test]
+    fn alternate_display_writes_pretty_form() {
+        let packet: Packet = "[1,[],[2,[3]]]".parse().unwrap();
+        let expected = "\
+[
+  1,
+  [],
+  [
+    2,
+    [
+      3
+    ]
+  ]
+]";
+        assert_eq!(format!("{:#}", packet), expected);
+    }
+
+    #[test]
+    fn explain_cmp_matches_walkthrough() {
+        let explain = |left: &str, right: &str| {
+            let explanation = explain_cmp(&left.parse().unwrap(), &right.parse().unwrap());
+            explanation.to_string()
+        };
+
+        let expected = "\
+- Compare [[1],[2,3,4]] vs [[1],4]
+  - Compare [1] vs [1]
+    - Compare 1 vs 1
+  - Compare [2,3,4] vs 4
+    - Mixed types; convert right to [4] and retry comparison
+    - Compare [2,3,4] vs [4]
+      - Compare 2 vs 4
+        - Left side is smaller, so inputs are in the right order";
+        assert_eq!(explain("[[1],[2,3,4]]", "[[1],4]"), expected);
+
+        let expected = "\
+- Compare [9] vs [[8,7,6]]
+  - Compare 9 vs [8,7,6]
+    - Mixed types; convert left to [9] and retry comparison
+    - Compare [9] vs [8,7,6]
+      - Compare 9 vs 8
+        - Right side is smaller, so inputs are not in the right order";
+        assert_eq!(explain("[9]", "[[8,7,6]]"), expected);
+
+        let expected = "\
+- Compare [[[]]] vs [[]]
+  - Compare [[]] vs []
+    - Right side ran out of items, so inputs are not in the right order";
+        assert_eq!(explain("[[[]]]", "[[]]"), expected);
+    }
+
+    #[test]
+    fn explain_cmp_agrees_with_cmp() {
+        let packets = input_generator2(INPUT);
+        for left in &packets {
+            for right in &packets {
+                assert_eq!(explain_cmp(left, right).ordering, left.cmp(right));
+            }
+        }
+    }
+
+    #[test]
+    fn parser_accepts_whitespaces() {
+        let packet: Packet = " [ 1 ,\n[2,  3] ] ".parse().unwrap();
+        assert_eq!(packet.to_string(), "[1,[2,3]]");
+        let pretty: Packet = format!("{:#}", packet).parse().unwrap();
+        assert_eq!(pretty, packet);
+    }
+
+    #[test]
+    fn parser_reports_error_positions() {
+        let error = |offset, reason| Err(ParsingError { offset, reason });
+        assert_eq!("".parse::<Packet>(), error(0, ErrorReason::UnexpectedEnd));
+        assert_eq!("[1,[2]".parse::<Packet>(), error(6, ErrorReason::UnexpectedEnd));
+        assert_eq!("[1,2]]".parse::<Packet>(), error(5, ErrorReason::UnexpectedChar(']')));
+        assert_eq!("[1,a]".parse::<Packet>(), error(3, ErrorReason::UnexpectedChar('a')));
+        assert_eq!("[1,]".parse::<Packet>(), error(3, ErrorReason::UnexpectedChar(']')));
+        assert_eq!("[1 2]".parse::<Packet>(), error(3, ErrorReason::UnexpectedChar('2')));
+        assert_eq!("[99999999999999999999999]".parse::<Packet>(), error(1, ErrorReason::IntegerOverflow));
+        assert_eq!(
+            "[é]".parse::<Packet>().unwrap_err().to_string(),
+            "Parsing error: unexpected char 'é' at offset 1",
+        );
+    }
+
+    #[test]
+    fn packets_convert_from_and_to_json() {
+        let json: Value = serde_json::from_str("[1, [2, [3, []]], 4]").unwrap();
+        let packet = Packet::try_from(&json).unwrap();
+        assert_eq!(packet, "[1,[2,[3,[]]],4]".parse().unwrap());
+        assert_eq!(Value::from(&packet), json);
+        assert_eq!(Packet::try_from(&json!([1, -2])), Err(JsonError { value: json!(-2) }));
+        assert_eq!(Packet::try_from(&json!([1, "a"])), Err(JsonError { value: json!("a") }));
+    }
+
+    /// Pseudo-random packets nested up to `depth` levels, from a linear congruential generator so
+    /// that failures are reproducible.
+    fn generate_packet(seed: &mut u64, depth: usize) -> Packet {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let roll = (*seed >> 32) as usize;
+        if depth == 0 || roll % 3 == 0 {
+            Packet::Int(roll >> 2)
+        } else {
+            Packet::List((0..roll / 3 % 6).map(|_| generate_packet(seed, depth - 1)).collect())
+        }
+    }
+
+    fn generated_lists() -> impl Iterator<Item=Packet> {
+        let mut seed = 2022;
+        (0..500).map(move |_| {
+            let length = (seed >> 40) as usize % 8;
+            Packet::List((0..length).map(|_| generate_packet(&mut seed, 4)).collect())
+        })
+    }
+
+    #[test]
+    fn parse_display_round_trips() {
+        for packet in generated_lists() {
+            assert_eq!(packet.to_string().parse::<Packet>().unwrap(), packet);
+            assert_eq!(format!("{:#}", packet).parse::<Packet>().unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn json_round_trips() {
+        for packet in generated_lists() {
+            assert_eq!(Packet::try_from(&Value::from(&packet)).unwrap(), packet);
+        }
+    }
 }
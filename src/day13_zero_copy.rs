@@ -0,0 +1,238 @@
+//! Compare packets straight from the input text, without building the packet trees
+//!
+//! cargo-aoc can't hand borrowed input to a solver, so the generators still copy the whole input
+//! text once; everything after that only borrows from the copy.
+use std::cmp::Ordering;
+use std::iter;
+
+use aoc_runner_derive::{aoc, aoc_generator};
+
+/// A packet borrowed from the input text, either an integer or a bracketed list.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketRef<'a> {
+    text: &'a [u8],
+}
+
+impl<'a> PacketRef<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text: trim(text.as_bytes()),
+        }
+    }
+
+    #[inline]
+    fn is_list(&self) -> bool {
+        self.text.first() == Some(&b'[')
+    }
+
+    /// Significant digits of an integer, so that integers of any size compare by length first.
+    #[inline]
+    fn digits(&self) -> &'a [u8] {
+        let end = self.text.iter().position(|byte| !byte.is_ascii_digit()).unwrap_or(self.text.len());
+        let start = self.text[..end].iter().position(|digit| *digit != b'0').unwrap_or(end);
+        &self.text[start..end]
+    }
+
+    fn elements(&self) -> Elements<'a> {
+        let end = self.text.iter().rposition(|byte| *byte == b']').unwrap_or(self.text.len());
+        Elements {
+            rest: trim(&self.text[1..end.max(1)]),
+        }
+    }
+}
+
+/// Strip the whitespaces the packet parser allows around tokens.
+fn trim(mut text: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = text {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        text = rest;
+    }
+    while let [rest @ .., last] = text {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        text = rest;
+    }
+    text
+}
+
+/// Top-level elements of a list, split on the commas found at depth 0.
+struct Elements<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = PacketRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let mut depth = 0;
+        let end = self.rest.iter()
+            .position(|byte| match byte {
+                b'[' => {
+                    depth += 1;
+                    false
+                }
+                b']' => {
+                    depth -= 1;
+                    false
+                }
+                b',' => depth == 0,
+                _ => false,
+            })
+            .unwrap_or(self.rest.len());
+
+        let (text, rest) = self.rest.split_at(end);
+        self.rest = trim(rest.get(1..).unwrap_or_default());
+        Some(PacketRef { text: trim(text) })
+    }
+}
+
+impl Ord for PacketRef<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.is_list(), other.is_list()) {
+            (false, false) => {
+                let (left, right) = (self.digits(), other.digits());
+                left.len().cmp(&right.len()).then_with(|| left.cmp(right))
+            }
+            (true, true) => self.elements().cmp(other.elements()),
+            (true, false) => self.elements().cmp(iter::once(*other)),
+            (false, true) => iter::once(*self).cmp(other.elements()),
+        }
+    }
+}
+
+impl PartialOrd for PacketRef<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PacketRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PacketRef<'_> {}
+
+/// Solutions work on the raw text, the generators only hand it over.
+#[aoc_generator(day13, part1, zero_copy)]
+pub fn input_generator1(input: &str) -> String {
+    input.to_string()
+}
+
+#[aoc_generator(day13, part2, zero_copy)]
+pub fn input_generator2(input: &str) -> String {
+    input.to_string()
+}
+
+#[aoc(day13, part1, zero_copy)]
+pub fn solve_part1(input: &str) -> usize {
+    input.split("\n\n")
+        .map(|block| {
+            let mut packets = block.lines().map(PacketRef::new);
+            (packets.next().unwrap(), packets.next().unwrap())
+        })
+        .enumerate()
+        .filter(|(_, (p1, p2))| p1 < p2)
+        .map(|(i, _)| i + 1)
+        .sum()
+}
+
+#[aoc(day13, part2, zero_copy)]
+pub fn solve_part2(input: &str) -> usize {
+    let p2 = PacketRef::new("[[2]]");
+    let p6 = PacketRef::new("[[6]]");
+    let mut packets = input.lines()
+        .filter(|line| !line.is_empty())
+        .map(PacketRef::new)
+        .chain([p2, p6])
+        .collect::<Vec<_>>();
+    packets.sort();
+    let i2 = packets.binary_search(&p2).unwrap() + 1;
+    let i6 = packets.binary_search(&p6).unwrap() + 1;
+    i2 * i6
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::day13::Packet;
+
+    use super::{PacketRef, solve_part1, solve_part2};
+
+    static INPUT: &str = "\
+[1,1,3,1,1]
+[1,1,5,1,1]
+
+[[1],[2,3,4]]
+[[1],4]
+
+[9]
+[[8,7,6]]
+
+[[4,4],4,4]
+[[4,4],4,4,4]
+
+[7,7,7,7]
+[7,7,7]
+
+[]
+[3]
+
+[[[]]]
+[[]]
+
+[1,[2,[3,[4,[5,6,7]]]],8,9]
+[1,[2,[3,[4,[5,6,0]]]],8,9]
+";
+
+    #[test]
+    fn solver_part1_match_example() {
+        assert_eq!(solve_part1(INPUT), 13);
+    }
+
+    #[test]
+    fn solver_part2_match_example() {
+        assert_eq!(solve_part2(INPUT), 140);
+    }
+
+    #[test]
+    fn comparison_agrees_with_packet() {
+        let lines = INPUT.lines().filter(|line| !line.is_empty()).collect::<Vec<_>>();
+        for left in &lines {
+            for right in &lines {
+                let expected = left.parse::<Packet>().unwrap().cmp(&right.parse().unwrap());
+                assert_eq!(PacketRef::new(left).cmp(&PacketRef::new(right)), expected, "{} vs {}", left, right);
+            }
+        }
+    }
+
+    #[test]
+    fn comparison_skips_whitespaces() {
+        let spaced = ["[1, 2]", " [ [1] , [2, 3,4 ] ] ", "[ ]", "[[ ], 3 ]"];
+        let compact = ["[1,2]", "[[1],[2,3,4]]", "[]", "[[],3]"];
+        for (left, spaced_left) in compact.iter().zip(spaced) {
+            for right in compact {
+                let expected = left.parse::<Packet>().unwrap().cmp(&right.parse().unwrap());
+                assert_eq!(spaced_left.parse::<Packet>().unwrap().cmp(&right.parse().unwrap()), expected);
+                let actual = PacketRef::new(spaced_left).cmp(&PacketRef::new(right));
+                assert_eq!(actual, expected, "{} vs {}", spaced_left, right);
+            }
+        }
+    }
+
+    #[test]
+    fn comparison_handles_large_integers() {
+        let large = PacketRef::new("[99999999999999999999999]");
+        assert!(PacketRef::new("[99999999999999999999998]") < large);
+        assert!(PacketRef::new("[100000000000000000000000]") > large);
+        assert_eq!(PacketRef::new("[007]"), PacketRef::new("[7]"));
+        assert_eq!(PacketRef::new("[0]"), PacketRef::new("[00]"));
+    }
+}
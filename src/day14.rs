@@ -1,120 +1,363 @@
 use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 use aoc_runner_derive::{aoc, aoc_generator};
 
-use crate::utils::Point;
+use crate::utils::{ParsePointError, Point};
 
-fn range(from: &Point<usize>, to: &Point<usize>) -> Vec<Point<usize>> {
-    if from.x < to.x {
-        (from.x..=to.x).zip([from.y].iter().cycle()).map(|(x, y)| Point { x, y: *y }).collect()
-    } else if from.x > to.x {
-        (to.x..=from.x).zip([from.y].iter().cycle()).map(|(x, y)| Point { x, y: *y }).collect()
-    } else if from.y < to.y {
-        [from.x].iter().cycle().zip(from.y..=to.y).map(|(x, y)| Point { x: *x, y }).collect()
-    } else if from.y > to.y {
-        [from.x].iter().cycle().zip(to.y..=from.y).map(|(x, y)| Point { x: *x, y }).collect()
-    } else {
-        [from.clone()].into_iter().collect()
+/// A polyline of rock as written in the input, e.g. `498,4 -> 498,6 -> 496,6`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RockPath {
+    pub points: Vec<Point<isize>>,
+}
+
+impl RockPath {
+    /// All the rock cells covered by the path, segment ends included.
+    pub fn blocks(&self) -> impl Iterator<Item=Point<isize>> + '_ {
+        self.points.windows(2).flat_map(|ab| {
+            let (from, to) = (ab[0], ab[1]);
+            let (dx, dy) = ((to.x - from.x).signum(), (to.y - from.y).signum());
+            let steps = isize::max((to.x - from.x).abs(), (to.y - from.y).abs());
+            (0..=steps).map(move |i| Point { x: from.x + i * dx, y: from.y + i * dy })
+        }).chain(self.points.first().copied().filter(|_| self.points.len() == 1))
+    }
+}
+
+impl FromStr for RockPath {
+    type Err = ParsingError;
+
+    /// Errors are reported on line 1, see `parse_paths` for multi-line input.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |index, kind| ParsingError { line: 1, index, kind };
+
+        let points = s.split(" -> ")
+            .enumerate()
+            .map(|(i, part)| {
+                let point: Point<isize> = part.parse().map_err(|err| error(i + 1, PathErrorKind::InvalidPoint(err)))?;
+                if point.x < 0 || point.y < 0 {
+                    return Err(error(i + 1, PathErrorKind::NegativeCoordinate(point)));
+                }
+                Ok(point)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some((i, ab)) = points.windows(2).enumerate().find(|(_, ab)| ab[0].x != ab[1].x && ab[0].y != ab[1].y) {
+            return Err(error(i + 1, PathErrorKind::DiagonalSegment(ab[0], ab[1])));
+        }
+
+        Ok(Self { points })
     }
 }
 
+impl Display for RockPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, point) in self.points.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", point)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum PathErrorKind {
+    InvalidPoint(ParsePointError),
+    NegativeCoordinate(Point<isize>),
+    DiagonalSegment(Point<isize>, Point<isize>),
+    Empty,
+}
+
+/// Where and why a rock path failed to parse. `index` is 1-based and counts points for point
+/// errors and segments for `DiagonalSegment`. Both are 0 for `Empty`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParsingError {
+    pub line: usize,
+    pub index: usize,
+    pub kind: PathErrorKind,
+}
+
+impl Display for ParsingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            PathErrorKind::InvalidPoint(err) =>
+                write!(f, "Parsing error: line {}, point {}: {}", self.line, self.index, err),
+            PathErrorKind::NegativeCoordinate(point) =>
+                write!(f, "Parsing error: line {}, point {}: negative coordinate in {}", self.line, self.index, point),
+            PathErrorKind::DiagonalSegment(from, to) =>
+                write!(f, "Parsing error: line {}, segment {}: diagonal segment from {} to {}", self.line, self.index, from, to),
+            PathErrorKind::Empty => write!(f, "Parsing error: no rock path"),
+        }
+    }
+}
+
+impl Error for ParsingError {}
+
+pub fn parse_paths(input: &str) -> Result<Vec<RockPath>, ParsingError> {
+    let paths = input.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| line.parse().map_err(|err| ParsingError { line: i + 1, ..err }))
+        .collect::<Result<Vec<RockPath>, _>>()?;
+
+    if paths.is_empty() {
+        return Err(ParsingError { line: 0, index: 0, kind: PathErrorKind::Empty });
+    }
+    Ok(paths)
+}
+
+/// Above this many cells in the bounding box, rocks and sand are kept in a `HashSet` instead.
+const DENSE_MAX_CELLS: usize = 1 << 24;
+
+/// Rocks and resting sand. `limit` is the row of the lowest rock, 0 when there is none.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Space {
+    pub limit: isize,
+    storage: Storage,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
-struct Space {
-    limit: usize,
-    blocks: HashSet<Point<usize>>,
+enum Storage {
+    Sparse(HashSet<Point<isize>>),
+    Dense(Bitmap),
+}
+
+/// `(min_x, max_x, min_y, max_y)` of the blocks, all 0 when there are none.
+fn bounds(blocks: &HashSet<Point<isize>>) -> (isize, isize, isize, isize) {
+    let (xs, ys) = (blocks.iter().map(|p| p.x), blocks.iter().map(|p| p.y));
+    (
+        xs.clone().min().unwrap_or(0),
+        xs.max().unwrap_or(0),
+        ys.clone().min().unwrap_or(0),
+        ys.max().unwrap_or(0),
+    )
 }
 
 impl Space {
-    fn new(blocks: HashSet<Point<usize>>) -> Self {
-        let limit = dbg!(blocks.iter().map(|p| p.y).max().unwrap());
+    pub fn new(blocks: HashSet<Point<isize>>) -> Self {
+        let (min_x, max_x, min_y, limit) = bounds(&blocks);
+
+        match dense_cells(min_x, max_x, isize::min(min_y, 0), limit + 1) {
+            Some(cells) if cells <= DENSE_MAX_CELLS => Self::dense(blocks),
+            _ => Self::sparse(blocks),
+        }
+    }
+
+    pub fn from_paths(paths: &[RockPath]) -> Self {
+        Self::new(paths.iter().flat_map(RockPath::blocks).collect())
+    }
+
+    pub fn sparse(blocks: HashSet<Point<isize>>) -> Self {
+        Self {
+            limit: bounds(&blocks).3,
+            storage: Storage::Sparse(blocks),
+        }
+    }
+
+    pub fn dense(blocks: HashSet<Point<isize>>) -> Self {
+        let (min_x, max_x, min_y, limit) = bounds(&blocks);
+
+        let mut bitmap = Bitmap::new(min_x, max_x, isize::min(min_y, 0), limit + 1);
+        for block in blocks {
+            bitmap.insert(block);
+        }
 
         Self {
             limit,
-            blocks,
+            storage: Storage::Dense(bitmap),
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, point: &Point<isize>) -> bool {
+        match &self.storage {
+            Storage::Sparse(blocks) => blocks.contains(point),
+            Storage::Dense(bitmap) => bitmap.contains(point),
+        }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, point: Point<isize>) {
+        match &mut self.storage {
+            Storage::Sparse(blocks) => {
+                blocks.insert(point);
+            }
+            Storage::Dense(bitmap) => bitmap.insert(point),
         }
     }
 }
 
-#[aoc_generator(day14)]
-fn input_generator(input: &str) -> Space {
-    let blocks = input.lines()
-        .flat_map(|line|
-            line.split(" -> ")
-                .map(|point| point.parse().unwrap())
-                .collect::<Vec<Point<usize>>>()
-                .windows(2)
-                .map(|ab| range(&ab[0], &ab[1]))
-                .collect::<Vec<_>>()
-        )
-        .flatten()
-        .collect();
-    Space::new(blocks)
+/// Number of cells of the bitmap `Space::dense` allocates for this bounding box, if it fits in
+/// memory at all.
+fn dense_cells(min_x: isize, max_x: isize, min_y: isize, max_y: isize) -> Option<usize> {
+    let width = usize::try_from(max_x.checked_sub(min_x)?.checked_add(1)?).ok()?;
+    let height = usize::try_from(max_y.checked_sub(min_y)?.checked_add(1)?).ok()?;
+    width.checked_mul(height)
 }
 
-#[aoc(day14, part1)]
-fn solve_part1(space: &Space) -> usize {
-    let mut space = space.clone();
-    let mut count = 0;
+/// Rows of bits covering `offset..offset + 64 * words` on the x axis and starting at `top` on the
+/// y axis. The offset is kept aligned on 64 so that widening only adds whole words on either side
+/// of the rows.
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Bitmap {
+    offset: isize,
+    top: isize,
+    words: usize,
+    rows: Vec<Vec<u64>>,
+}
 
-    loop {
-        let mut block = Point { x: 500, y: 0 };
-        count += 1;
+impl Bitmap {
+    fn new(min_x: isize, max_x: isize, min_y: isize, max_y: isize) -> Self {
+        let offset = min_x.div_euclid(64) * 64;
+        let words = ((max_x - offset) / 64 + 1) as usize;
 
-        loop {
-            if block.y > space.limit {
-                return count - 1;
-            }
+        Self {
+            offset,
+            top: min_y,
+            words,
+            rows: vec![vec![0; words]; (max_y - min_y + 1) as usize],
+        }
+    }
 
-            if !space.blocks.contains(&Point { x: block.x, y: block.y + 1 }) {
-                block.y += 1;
-            } else if !space.blocks.contains(&Point { x: block.x - 1, y: block.y + 1 }) {
-                block.x -= 1;
-                block.y += 1;
-            } else if !space.blocks.contains(&Point { x: block.x + 1, y: block.y + 1 }) {
-                block.x += 1;
-                block.y += 1;
-            } else {
-                space.blocks.insert(block.clone());
-                break;
+    #[inline]
+    fn contains(&self, point: &Point<isize>) -> bool {
+        if point.x < self.offset || point.y < self.top {
+            return false;
+        }
+        let column = (point.x - self.offset) as usize;
+        self.rows.get((point.y - self.top) as usize)
+            .and_then(|row| row.get(column / 64))
+            .map_or(false, |word| (word >> (column % 64)) & 1 == 1)
+    }
+
+    fn insert(&mut self, point: Point<isize>) {
+        self.widen(point.x);
+        if point.y < self.top {
+            let missing = (self.top - point.y) as usize;
+            self.rows.splice(0..0, std::iter::repeat(vec![0; self.words]).take(missing));
+            self.top = point.y;
+        }
+        let row = (point.y - self.top) as usize;
+        if row >= self.rows.len() {
+            self.rows.resize(row + 1, vec![0; self.words]);
+        }
+        let column = (point.x - self.offset) as usize;
+        self.rows[row][column / 64] |= 1 << (column % 64);
+    }
+
+    /// Make room for column `x`, growing by at least the current width to keep widening amortised.
+    fn widen(&mut self, x: isize) {
+        if x < self.offset {
+            let missing = ((self.offset - x) as usize + 63) / 64;
+            let extra = usize::max(missing, self.words);
+            for row in self.rows.iter_mut() {
+                row.splice(0..0, std::iter::repeat(0).take(extra));
+            }
+            self.offset -= extra as isize * 64;
+            self.words += extra;
+        } else if x >= self.offset + self.words as isize * 64 {
+            let missing = ((x - self.offset) / 64) as usize + 1 - self.words;
+            let extra = usize::max(missing, self.words);
+            for row in self.rows.iter_mut() {
+                row.resize(self.words + extra, 0);
             }
+            self.words += extra;
         }
     }
 }
 
-#[aoc(day14, part2)]
-fn solve_part2(space: &Space) -> usize {
+/// What lies below the lowest rock.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Floor {
+    /// Nothing: the first grain falling past the lowest rock ends the simulation.
+    Abyss,
+    /// An infinite floor this many rows below the lowest rock: sand piles up until the source is
+    /// blocked.
+    Infinite(isize),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SandRules {
+    pub source: Point<isize>,
+    pub floor: Floor,
+    /// Horizontal offsets of the cells one row below that a grain tries, in order of preference.
+    pub fall_order: Vec<isize>,
+}
+
+impl Default for SandRules {
+    fn default() -> Self {
+        Self {
+            source: SOURCE,
+            floor: Floor::Abyss,
+            fall_order: vec![0, -1, 1],
+        }
+    }
+}
+
+pub const SOURCE: Point<isize> = Point { x: 500, y: 0 };
+
+/// Pour sand until it either falls into the abyss or blocks the source, and count resting grains.
+pub fn simulate(space: &Space, rules: &SandRules) -> usize {
     let mut space = space.clone();
+    let floor = match rules.floor {
+        Floor::Abyss => None,
+        Floor::Infinite(offset) => Some(space.limit + offset),
+    };
     let mut count = 0;
 
-    loop {
-        let mut block = Point { x: 500, y: 0 };
-        count += 1;
+    while !space.contains(&rules.source) {
+        let mut block = rules.source;
 
         loop {
-            if block.y == space.limit + 1 {
-                space.blocks.insert(block.clone());
-                break;
-            } else if !space.blocks.contains(&Point { x: block.x, y: block.y + 1 }) {
-                block.y += 1;
-            } else if !space.blocks.contains(&Point { x: block.x - 1, y: block.y + 1 }) {
-                block.x -= 1;
-                block.y += 1;
-            } else if !space.blocks.contains(&Point { x: block.x + 1, y: block.y + 1 }) {
-                block.x += 1;
-                block.y += 1;
-            } else if block.y == 0 {
+            if floor.is_none() && block.y > space.limit {
                 return count;
-            } else {
-                space.blocks.insert(block.clone());
-                break;
+            }
+
+            let next = rules.fall_order.iter()
+                .map(|dx| Point { x: block.x + dx, y: block.y + 1 })
+                .find(|point| !space.contains(point) && floor.map_or(true, |floor| point.y < floor));
+
+            match next {
+                Some(next) => block = next,
+                None => {
+                    space.insert(block);
+                    count += 1;
+                    break;
+                }
             }
         }
     }
+
+    count
+}
+
+#[aoc_generator(day14)]
+pub fn input_generator(input: &str) -> Result<Space, ParsingError> {
+    parse_paths(input).map(|paths| Space::from_paths(&paths))
+}
+
+#[aoc(day14, part1)]
+fn solve_part1(space: &Space) -> usize {
+    simulate(space, &SandRules::default())
+}
+
+#[aoc(day14, part2)]
+fn solve_part2(space: &Space) -> usize {
+    simulate(space, &SandRules { floor: Floor::Infinite(2), ..SandRules::default() })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{input_generator, solve_part1, solve_part2, Space, Point};
+    use std::collections::HashSet;
+
+    use crate::utils::Point;
+
+    use crate::utils::ParsePointError;
+
+    use super::{Floor, input_generator, parse_paths, ParsingError, PathErrorKind, RockPath, SandRules, simulate, solve_part1, solve_part2, Space, Storage};
 
     static INPUT: &str = "\
 498,4 -> 498,6 -> 496,6
@@ -122,13 +365,116 @@ mod tests {
 
     #[test]
     fn solver_part1_match_example() {
-        assert_eq!(solve_part1(&input_generator(INPUT)), 24);
+        assert_eq!(solve_part1(&input_generator(INPUT).unwrap()), 24);
     }
 
     #[test]
     fn solver_part2_match_example() {
-        assert_eq!(solve_part2(&input_generator(INPUT)), 93);
+        assert_eq!(solve_part2(&input_generator(INPUT).unwrap()), 93);
+    }
+
+    #[test]
+    fn dense_and_sparse_storages_agree() {
+        let space = input_generator(INPUT).unwrap();
+        let blocks: HashSet<_> = (0..12)
+            .flat_map(|y| (490..510).map(move |x| Point { x, y }))
+            .filter(|point| space.contains(point))
+            .collect();
+        assert_eq!(solve_part1(&Space::sparse(blocks.clone())), 24);
+        assert_eq!(solve_part1(&Space::dense(blocks.clone())), 24);
+        assert_eq!(solve_part2(&Space::sparse(blocks.clone())), 93);
+        assert_eq!(solve_part2(&Space::dense(blocks)), 93);
+    }
+
+    #[test]
+    fn space_picks_storage_from_allocated_rows() {
+        let deep: HashSet<_> = (0..10).map(|x| Point { x, y: 3_000_000 }).collect();
+        assert!(matches!(Space::new(deep).storage, Storage::Sparse(_)));
+
+        let shallow: HashSet<_> = (0..10).map(|x| Point { x, y: 3_000 }).collect();
+        assert!(matches!(Space::new(shallow).storage, Storage::Dense(_)));
+
+        let huge: HashSet<_> = [Point { x: isize::MIN, y: 0 }, Point { x: isize::MAX, y: 1 }].into();
+        assert!(matches!(Space::new(huge).storage, Storage::Sparse(_)));
+    }
+
+    #[test]
+    fn dense_storage_widens_on_both_sides() {
+        let mut space = Space::dense([Point { x: 500, y: 3 }].into_iter().collect());
+        space.insert(Point { x: 3, y: 0 });
+        space.insert(Point { x: 1_000, y: 5 });
+        assert!(space.contains(&Point { x: 500, y: 3 }));
+        assert!(space.contains(&Point { x: 3, y: 0 }));
+        assert!(space.contains(&Point { x: 1_000, y: 5 }));
+        assert!(!space.contains(&Point { x: 4, y: 0 }));
+        assert!(!space.contains(&Point { x: 1_000, y: 6 }));
+    }
+
+    #[test]
+    fn simulate_handles_negative_coordinates() {
+        let space = Space::new((-1..=1).map(|x| Point { x, y: 3 }).collect());
+        let rules = SandRules { source: Point { x: 0, y: 0 }, ..SandRules::default() };
+        assert_eq!(simulate(&space, &rules), 1);
+        let rules = SandRules { floor: Floor::Infinite(2), ..rules };
+        assert_eq!(simulate(&space, &rules), 21);
     }
-}
 
+    #[test]
+    fn simulate_follows_fall_order() {
+        let mirrored = "\
+502,4 -> 502,6 -> 504,6
+497,4 -> 498,4 -> 498,9 -> 506,9";
+        let rules = SandRules { fall_order: vec![0, 1, -1], ..SandRules::default() };
+        assert_eq!(simulate(&input_generator(mirrored).unwrap(), &rules), 24);
+        let rules = SandRules { floor: Floor::Infinite(2), ..rules };
+        assert_eq!(simulate(&input_generator(mirrored).unwrap(), &rules), 93);
+    }
 
+    #[test]
+    fn rock_paths_round_trip() {
+        let paths = parse_paths(INPUT).unwrap();
+        assert_eq!(paths[0], RockPath {
+            points: vec![Point { x: 498, y: 4 }, Point { x: 498, y: 6 }, Point { x: 496, y: 6 }],
+        });
+        let serialized = paths.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n");
+        assert_eq!(serialized, INPUT);
+    }
+
+    #[test]
+    fn rock_path_parser_reports_errors() {
+        assert_eq!(
+            parse_paths("498,4 -> 498,6\n1,1 -> 2,1 -> 3,3"),
+            Err(ParsingError { line: 2, index: 2, kind: PathErrorKind::DiagonalSegment(Point { x: 2, y: 1 }, Point { x: 3, y: 3 }) }),
+        );
+        assert_eq!(
+            parse_paths("498,4 -> 498"),
+            Err(ParsingError { line: 1, index: 2, kind: PathErrorKind::InvalidPoint(ParsePointError::MissingCoordinate("y")) }),
+        );
+        assert_eq!(
+            parse_paths("498,4 -> -1,4"),
+            Err(ParsingError { line: 1, index: 2, kind: PathErrorKind::NegativeCoordinate(Point { x: -1, y: 4 }) }),
+        );
+        assert_eq!(
+            "1,1 -> 1,1 -> 3,3".parse::<RockPath>().unwrap_err().to_string(),
+            "Parsing error: line 1, segment 2: diagonal segment from 1,1 to 3,3",
+        );
+    }
+
+    #[test]
+    fn generator_reports_errors_instead_of_panicking() {
+        let empty = ParsingError { line: 0, index: 0, kind: PathErrorKind::Empty };
+        assert_eq!(parse_paths(""), Err(empty.clone()));
+        assert_eq!(input_generator("\n").unwrap_err(), empty);
+        assert_eq!(empty.to_string(), "Parsing error: no rock path");
+        assert_eq!(input_generator("498,4 -> 498,x").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn empty_space_lets_sand_fall() {
+        for space in [Space::new(HashSet::new()), Space::sparse(HashSet::new()), Space::dense(HashSet::new())] {
+            assert_eq!(space.limit, 0);
+            assert_eq!(simulate(&space, &SandRules::default()), 0);
+            assert_eq!(simulate(&space, &SandRules { floor: Floor::Infinite(2), ..SandRules::default() }), 4);
+        }
+    }
+}
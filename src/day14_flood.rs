@@ -0,0 +1,82 @@
+//! Faster solutions that avoid replaying every grain from the source
+use aoc_runner_derive::aoc;
+
+use crate::day14::{input_generator, SOURCE, Space};
+use crate::utils::Point;
+
+/// Keep the fall path of the previous grain on a stack: the next grain follows the exact same
+/// path until the cell where the previous one came to rest, so it can start from there.
+#[aoc(day14, part1, path_stack)]
+pub fn solve_part1(space: &Space) -> usize {
+    let mut space = space.clone();
+    let mut path = vec![SOURCE];
+    let mut count = 0;
+
+    while let Some(block) = path.last().copied() {
+        if block.y > space.limit {
+            break;
+        }
+
+        let next = [block.x, block.x - 1, block.x + 1]
+            .into_iter()
+            .map(|x| Point { x, y: block.y + 1 })
+            .find(|point| !space.contains(point));
+
+        match next {
+            Some(next) => path.push(next),
+            None => {
+                space.insert(block);
+                path.pop();
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+/// With a floor, a cell ends up filled iff it isn't rock and one of the three cells above it is
+/// filled, so the resting sand can be computed row by row.
+#[aoc(day14, part2, flood)]
+pub fn solve_part2(space: &Space) -> usize {
+    let depth = (space.limit + 1 - SOURCE.y) as usize;
+    let width = 2 * depth + 1;
+    let left = SOURCE.x - depth as isize;
+
+    let mut row = vec![false; width];
+    row[depth] = true;
+    let mut count = 1;
+
+    for y in SOURCE.y + 1..=space.limit + 1 {
+        row = (0..width)
+            .map(|i| {
+                let fed = row[i]
+                    || (i > 0 && row[i - 1])
+                    || (i + 1 < width && row[i + 1]);
+                fed && !space.contains(&Point { x: left + i as isize, y })
+            })
+            .collect();
+        count += row.iter().filter(|filled| **filled).count();
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{input_generator, solve_part1, solve_part2};
+
+    static INPUT: &str = "\
+498,4 -> 498,6 -> 496,6
+503,4 -> 502,4 -> 502,9 -> 494,9";
+
+    #[test]
+    fn solver_part1_match_example() {
+        assert_eq!(solve_part1(&input_generator(INPUT).unwrap()), 24);
+    }
+
+    #[test]
+    fn solver_part2_match_example() {
+        assert_eq!(solve_part2(&input_generator(INPUT).unwrap()), 93);
+    }
+}
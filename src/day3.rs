@@ -1,6 +1,5 @@
 use std::collections::HashSet;
 use std::hash::Hash;
-use std::ops::Deref;
 
 use aoc_runner_derive::{aoc, aoc_generator};
 
@@ -37,7 +36,7 @@ fn common<'a, I, C, T>(mut iterator: I) -> T
 {
     let init = iterator.next().unwrap().into_iter().collect::<HashSet<_>>();
     let set = iterator.fold(init, |acc, elems| &acc & &elems.into_iter().collect());
-    set.iter().next().unwrap().deref().clone()
+    (*set.iter().next().unwrap()).clone()
 }
 
 pub fn priority(item: u8) -> u32 {
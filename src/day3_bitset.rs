@@ -60,6 +60,6 @@ CrZsJsPPZsGzwwsLwLmpwMDw";
 
     #[test]
     fn reversible_bitset() {
-        assert_eq!(extract_item(into_bitset(&[b'x'])), b'x')
+        assert_eq!(extract_item(into_bitset(b"x")), b'x')
     }
 }
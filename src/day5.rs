@@ -1,16 +1,156 @@
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 use aoc_runner_derive::{aoc, aoc_generator};
 
-type Input = (Stacks, Vec<Instruction>);
+type Input = (Yard, Vec<Instruction>);
 
 type Stacks = Vec<Vec<char>>;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub struct Instruction {
-    count: usize,
-    from: usize,
-    to: usize,
+    pub count: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Stacks of crates, numbered from 1 like in the instructions.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Yard {
+    stacks: Stacks,
+}
+
+impl Yard {
+    pub fn new(stacks: Stacks) -> Self {
+        Self { stacks }
+    }
+
+    pub fn stacks(&self) -> &[Vec<char>] {
+        &self.stacks
+    }
+
+    /// Crates on top of each non-empty stack.
+    pub fn tops(&self) -> String {
+        self.stacks.iter().filter_map(|stack| stack.last()).collect()
+    }
+
+    /// Remove the `count` topmost crates of a stack, keeping their order.
+    ///
+    /// # Panics
+    ///
+    /// If the stack doesn't exist or holds less than `count` crates.
+    pub fn lift(&mut self, from: usize, count: usize) -> Vec<char> {
+        let stack = &mut self.stacks[from - 1];
+        stack.split_off(stack.len() - count)
+    }
+
+    /// Put crates on top of a stack, the last one ending on top.
+    pub fn lower(&mut self, to: usize, crates: impl IntoIterator<Item=char>) {
+        self.stacks[to - 1].extend(crates)
+    }
+
+    pub fn apply(&mut self, crane: &impl Crane, instruction: &Instruction) {
+        crane.operate(self, instruction)
+    }
+
+    /// Apply every instruction, yielding the state of the yard after each of them.
+    pub fn replay<'a, C: Crane>(&self, crane: &'a C, instructions: &'a [Instruction]) -> Replay<'a, C> {
+        Replay { yard: self.clone(), crane, instructions: instructions.iter() }
+    }
+}
+
+/// Draw the yard the way the puzzle does.
+impl Display for Yard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let height = self.stacks.iter().map(Vec::len).max().unwrap_or(0);
+        for level in (0..height).rev() {
+            let row: Vec<_> = self
+                .stacks
+                .iter()
+                .map(|stack| stack.get(level).map_or("   ".to_string(), |item| format!("[{}]", item)))
+                .collect();
+            writeln!(f, "{}", row.join(" "))?;
+        }
+        let labels: Vec<_> = (1..=self.stacks.len()).map(|n| format!(" {} ", n)).collect();
+        write!(f, "{}", labels.join(" "))
+    }
+}
+
+/// Step by step application of instructions, see [`Yard::replay`].
+pub struct Replay<'a, C> {
+    yard: Yard,
+    crane: &'a C,
+    instructions: std::slice::Iter<'a, Instruction>,
+}
+
+impl<C: Crane> Iterator for Replay<'_, C> {
+    type Item = Yard;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let instruction = self.instructions.next()?;
+        self.yard.apply(self.crane, instruction);
+        Some(self.yard.clone())
+    }
+}
+
+pub trait Crane {
+    fn operate(&self, yard: &mut Yard, instruction: &Instruction);
+}
+
+/// Moves crates one at a time.
+pub struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn operate(&self, yard: &mut Yard, instruction: &Instruction) {
+        let crates = yard.lift(instruction.from, instruction.count);
+        yard.lower(instruction.to, crates.into_iter().rev());
+    }
+}
+
+/// Moves all the crates at once.
+pub struct CrateMover9001;
+
+impl Crane for CrateMover9001 {
+    fn operate(&self, yard: &mut Yard, instruction: &Instruction) {
+        let crates = yard.lift(instruction.from, instruction.count);
+        yard.lower(instruction.to, crates);
+    }
+}
+
+/// Moves at most `capacity` crates at once, so it behaves like a CrateMover 9000 with a capacity
+/// of 1 and like a CrateMover 9001 when the capacity is large enough.
+pub struct LimitedCrane {
+    capacity: usize,
+}
+
+impl LimitedCrane {
+    /// # Panics
+    ///
+    /// If the capacity is zero, as such a crane can't move anything.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "crane capacity can't be zero");
+        Self { capacity }
+    }
+}
+
+impl Crane for LimitedCrane {
+    fn operate(&self, yard: &mut Yard, instruction: &Instruction) {
+        let mut remaining = instruction.count;
+        while remaining > 0 {
+            let count = remaining.min(self.capacity);
+            let crates = yard.lift(instruction.from, count);
+            yard.lower(instruction.to, crates);
+            remaining -= count;
+        }
+    }
+}
+
+fn run(yard: &Yard, crane: &impl Crane, instructions: &[Instruction]) -> Yard {
+    let mut yard = yard.clone();
+    for instruction in instructions {
+        yard.apply(crane, instruction);
+    }
+    yard
 }
 
 impl FromStr for Instruction {
@@ -43,52 +183,37 @@ pub fn input_generator(input: &str) -> Input {
         .split('\n')
         .map(|line| line.parse().unwrap())
         .collect();
-    (stacks, instructions)
+    (Yard::new(stacks), instructions)
 }
 
 #[aoc(day5, part1)]
-pub fn solve_part1((stacks, instructions): &Input) -> String {
-    let mut stacks = stacks.clone();
-    for instruction in instructions {
-        for _ in 0..instruction.count {
-            let value = stacks[instruction.from - 1].pop().unwrap();
-            stacks[instruction.to - 1].push(value);
-        }
-    }
-    stacks.iter().map(|stack| stack.last().unwrap()).collect()
+pub fn solve_part1((yard, instructions): &Input) -> String {
+    run(yard, &CrateMover9000, instructions).tops()
 }
 
 #[aoc(day5, part2)]
-pub fn solve_part2((stacks, instructions): &Input) -> String {
-    let mut stacks = stacks.clone();
-    let mut values = vec![];
-    for instruction in instructions {
-        for _ in 0..instruction.count {
-            values.push(stacks[instruction.from - 1].pop().unwrap());
-        }
-        for _ in 0..instruction.count {
-            stacks[instruction.to - 1].push(values.pop().unwrap());
-        }
-    }
-    stacks.iter().map(|stack| stack.last().unwrap()).collect()
+pub fn solve_part2((yard, instructions): &Input) -> String {
+    run(yard, &CrateMover9001, instructions).tops()
 }
 
 #[cfg(test)]
 mod tests {
     use crate::day5::Instruction;
 
-    use super::{input_generator, solve_part1, solve_part2};
+    use super::{
+        CrateMover9000, CrateMover9001, input_generator, LimitedCrane, run, solve_part1, solve_part2, Yard,
+    };
 
     static INPUT: &str = "    [D]    \n[N] [C]    \n[Z] [M] [P]\n 1   2   3 \n\nmove 1 from 2 to 1\nmove 3 from 1 to 3\nmove 2 from 2 to 1\nmove 1 from 1 to 2";
 
     #[test]
     fn input_generator_builds_vec() {
         let expect = (
-            vec![
+            Yard::new(vec![
                 vec!['Z', 'N'],
                 vec!['M', 'C', 'D'],
                 vec!['P'],
-            ],
+            ]),
             vec![
                 Instruction { count: 1, from: 2, to: 1 },
                 Instruction { count: 3, from: 1, to: 3 },
@@ -108,5 +233,47 @@ mod tests {
     fn solver_part2_match_example() {
         assert_eq!(&solve_part2(&input_generator(INPUT)), "MCD");
     }
-}
 
+    #[test]
+    fn yard_display_matches_drawing() {
+        let (yard, _) = input_generator(INPUT);
+        assert_eq!(yard.to_string(), INPUT.split_once("\n\n").unwrap().0);
+    }
+
+    #[test]
+    fn replay_records_every_step() {
+        let (yard, instructions) = input_generator(INPUT);
+        let states: Vec<_> = yard.replay(&CrateMover9000, &instructions).collect();
+        assert_eq!(states.len(), instructions.len());
+        assert_eq!(states[0].tops(), "DCP");
+        assert_eq!(states[1].to_string(), "        [Z]\n        [N]\n    [C] [D]\n    [M] [P]\n 1   2   3 ");
+        assert_eq!(states[3].tops(), "CMZ");
+    }
+
+    #[test]
+    fn limited_crane_moves_batches() {
+        let (yard, instructions) = input_generator(INPUT);
+        assert_eq!(run(&yard, &LimitedCrane::new(2), &instructions).tops(), "MCZ");
+        assert_eq!(
+            run(&yard, &LimitedCrane::new(1), &instructions),
+            run(&yard, &CrateMover9000, &instructions),
+        );
+        assert_eq!(
+            run(&yard, &LimitedCrane::new(3), &instructions),
+            run(&yard, &CrateMover9001, &instructions),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "crane capacity can't be zero")]
+    fn limited_crane_rejects_zero_capacity() {
+        LimitedCrane::new(0);
+    }
+
+    #[test]
+    fn replay_ends_where_run_does() {
+        let (yard, instructions) = input_generator(INPUT);
+        let crane = LimitedCrane::new(2);
+        assert_eq!(yard.replay(&crane, &instructions).last(), Some(run(&yard, &crane, &instructions)));
+    }
+}
@@ -1,5 +1,11 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 
+const PACKET_WIDE: usize = 4;
+const MESSAGE_WIDE: usize = 14;
+
 #[aoc_generator(day6)]
 pub fn input_generator(input: &str) -> Vec<u8> {
     input.bytes().collect()
@@ -7,33 +13,216 @@ pub fn input_generator(input: &str) -> Vec<u8> {
 
 #[aoc(day6, part1)]
 pub fn solve_part1(input: &[u8]) -> usize {
-    solve(input, 4)
+    find_marker(input, PACKET_WIDE).expect("no start-of-packet marker")
 }
 
 #[aoc(day6, part2)]
 pub fn solve_part2(input: &[u8]) -> usize {
-    solve(input, 14)
+    find_marker(input, MESSAGE_WIDE).expect("no start-of-message marker")
+}
+
+#[aoc(day6, part1, streaming)]
+pub fn solve_part1_streaming(input: &[u8]) -> usize {
+    first_streamed(input, MarkerKind::StartOfPacket)
+}
+
+#[aoc(day6, part2, streaming)]
+pub fn solve_part2_streaming(input: &[u8]) -> usize {
+    first_streamed(input, MarkerKind::StartOfMessage)
 }
 
-fn solve(input: &[u8], wide: usize) -> usize {
+fn first_streamed(input: &[u8], kind: MarkerKind) -> usize {
+    MarkerDetector::new()
+        .markers(input)
+        .map(Result::unwrap)
+        .find(|marker| marker.kind == kind)
+        .expect("no marker")
+        .position
+}
+
+/// Position right after the first `wide` consecutive distinct bytes, if any.
+///
+/// The window slides over the input keeping a count per byte value and the number of distinct
+/// values it holds, so each byte is only looked at twice whatever the window size.
+pub fn find_marker(input: &[u8], wide: usize) -> Option<usize> {
+    if wide == 0 {
+        return Some(0);
+    }
+
+    let mut window = Window::new(wide);
     input
-        .windows(wide)
+        .iter()
         .enumerate()
-        .find(|(_, window)|  all_uniques(window))
-        .unwrap()
-        .0 + wide
+        .position(|(i, &byte)| window.push(byte, i.checked_sub(wide).map(|j| input[j])))
+        .map(|i| i + 1)
 }
 
-fn all_uniques(group: &[u8]) -> bool {
-    group
-        .iter()
-        .fold(0u32, |acc, elem| acc | (1 << (elem - b'a')))
-        .count_ones() as usize == group.len()
+/// Per byte counts of the last `wide` bytes of a stream.
+#[derive(Debug, Clone)]
+struct Window {
+    wide: usize,
+    counts: [usize; 256],
+    distinct: usize,
+}
+
+impl Window {
+    fn new(wide: usize) -> Self {
+        Self { wide, counts: [0; 256], distinct: 0 }
+    }
+
+    /// Slide the window over `byte`, `dropped` being the byte leaving it once it is full. Tell
+    /// whether the window now only holds distinct bytes.
+    fn push(&mut self, byte: u8, dropped: Option<u8>) -> bool {
+        self.counts[byte as usize] += 1;
+        if self.counts[byte as usize] == 1 {
+            self.distinct += 1;
+        }
+
+        if let Some(dropped) = dropped {
+            self.counts[dropped as usize] -= 1;
+            if self.counts[dropped as usize] == 0 {
+                self.distinct -= 1;
+            }
+        }
+
+        self.distinct == self.wide
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MarkerKind {
+    StartOfPacket,
+    StartOfMessage,
+}
+
+/// End position of a window of distinct bytes, counted from the start of the stream.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Marker {
+    pub kind: MarkerKind,
+    pub position: usize,
+}
+
+/// Incremental marker detection: bytes can be fed in any number of chunks and only the last
+/// bytes of the widest window are kept around.
+#[derive(Debug, Clone)]
+pub struct MarkerDetector {
+    packet: Window,
+    message: Window,
+    history: VecDeque<u8>,
+    keep: usize,
+    position: usize,
+}
+
+impl Default for MarkerDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkerDetector {
+    pub fn new() -> Self {
+        Self::with_widths(PACKET_WIDE, MESSAGE_WIDE)
+    }
+
+    /// # Panics
+    ///
+    /// If one of the widths is zero.
+    pub fn with_widths(packet: usize, message: usize) -> Self {
+        assert!(packet > 0 && message > 0, "marker windows can't be empty");
+        Self {
+            packet: Window::new(packet),
+            message: Window::new(message),
+            history: VecDeque::with_capacity(packet.max(message)),
+            keep: packet.max(message),
+            position: 0,
+        }
+    }
+
+    /// Number of bytes fed so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Feed one byte, returning the markers ending on it.
+    pub fn push(&mut self, byte: u8) -> impl Iterator<Item=Marker> {
+        let dropped = |wide: usize, history: &VecDeque<u8>| {
+            history.len().checked_sub(wide).map(|i| history[i])
+        };
+        let packet = self.packet.push(byte, dropped(self.packet.wide, &self.history));
+        let message = self.message.push(byte, dropped(self.message.wide, &self.history));
+
+        if self.history.len() == self.keep {
+            self.history.pop_front();
+        }
+        self.history.push_back(byte);
+        self.position += 1;
+
+        let position = self.position;
+        [(packet, MarkerKind::StartOfPacket), (message, MarkerKind::StartOfMessage)]
+            .into_iter()
+            .filter(|(found, _)| *found)
+            .map(move |(_, kind)| Marker { kind, position })
+    }
+
+    /// Feed a chunk of bytes, lazily returning the markers found in it.
+    pub fn feed<'a>(&'a mut self, chunk: &'a [u8]) -> impl Iterator<Item=Marker> + 'a {
+        chunk.iter().flat_map(move |&byte| self.push(byte))
+    }
+
+    /// Read the whole stream, yielding markers as they are found.
+    pub fn markers<R: Read>(self, reader: R) -> Markers<R> {
+        Markers {
+            reader,
+            detector: self,
+            buffer: vec![0; 8192],
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+/// Iterator over the markers of a stream, see [`MarkerDetector::markers`].
+pub struct Markers<R> {
+    reader: R,
+    detector: MarkerDetector,
+    buffer: Vec<u8>,
+    pending: VecDeque<Marker>,
+    done: bool,
+}
+
+impl<R: Read> Iterator for Markers<R> {
+    type Item = io::Result<Marker>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(marker) = self.pending.pop_front() {
+                return Some(Ok(marker));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.reader.read(&mut self.buffer) {
+                Ok(0) => self.done = true,
+                Ok(n) => self.pending.extend(self.detector.feed(&self.buffer[..n])),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{input_generator, solve_part1, solve_part2};
+    use std::io::{self, Read};
+
+    use super::{
+        find_marker, input_generator, Marker, MarkerDetector, MarkerKind, solve_part1, solve_part1_streaming,
+        solve_part2, solve_part2_streaming,
+    };
 
     static INPUT: &str = "nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg";
 
@@ -46,4 +235,109 @@ mod tests {
     fn solver_part2_match_example() {
         assert_eq!(solve_part2(&input_generator(INPUT)), 29);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn find_marker_match_examples() {
+        let examples = [
+            ("bvwbjplbgvbhsrlpgdmjqwftvncz", 5, 23),
+            ("nppdvjthqldpwncqszvftbrmjlhg", 6, 23),
+            ("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw", 11, 26),
+        ];
+        for (input, packet, message) in examples {
+            assert_eq!(find_marker(input.as_bytes(), 4), Some(packet));
+            assert_eq!(find_marker(input.as_bytes(), 14), Some(message));
+        }
+    }
+
+    #[test]
+    fn find_marker_works_on_any_byte() {
+        assert_eq!(find_marker(b"AAB{\x00\xff", 4), Some(5));
+        assert_eq!(find_marker(&[0xff, 0xff, 0xfe], 2), Some(3));
+    }
+
+    #[test]
+    fn find_marker_returns_none_without_marker() {
+        assert_eq!(find_marker(b"aaaaaaaa", 2), None);
+        assert_eq!(find_marker(b"abc", 4), None);
+        assert_eq!(find_marker(b"", 1), None);
+    }
+
+    #[test]
+    fn find_marker_handles_wide_windows() {
+        let input: Vec<u8> = (0..=255).chain(0..=255).collect();
+        assert_eq!(find_marker(&input, 256), Some(256));
+        assert_eq!(find_marker(&input, 257), None);
+        assert_eq!(find_marker(b"abc", 0), Some(0));
+    }
+
+    #[test]
+    fn streaming_solvers_match_example() {
+        assert_eq!(solve_part1_streaming(&input_generator(INPUT)), 10);
+        assert_eq!(solve_part2_streaming(&input_generator(INPUT)), 29);
+    }
+
+    #[test]
+    fn detector_yields_every_marker() {
+        let markers: Vec<_> = MarkerDetector::with_widths(2, 3).feed(b"aabcc").collect();
+        let expected = vec![
+            Marker { kind: MarkerKind::StartOfPacket, position: 3 },
+            Marker { kind: MarkerKind::StartOfPacket, position: 4 },
+            Marker { kind: MarkerKind::StartOfMessage, position: 4 },
+        ];
+        assert_eq!(markers, expected);
+    }
+
+    #[test]
+    fn detector_matches_find_marker_whatever_the_chunks() {
+        let input = b"zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw";
+        for size in 1..=input.len() {
+            let mut detector = MarkerDetector::new();
+            let markers: Vec<_> = input
+                .chunks(size)
+                .flat_map(|chunk| detector.feed(chunk).collect::<Vec<_>>())
+                .collect();
+            let first = |kind| markers.iter().find(|marker| marker.kind == kind).map(|marker| marker.position);
+            assert_eq!(first(MarkerKind::StartOfPacket), find_marker(input, 4));
+            assert_eq!(first(MarkerKind::StartOfMessage), find_marker(input, 14));
+            assert_eq!(detector.position(), input.len());
+        }
+    }
+
+    /// Reader handing out a few bytes at a time, failing once after the first read.
+    struct Trickle<'a> {
+        input: &'a [u8],
+        reads: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            if self.reads == 2 {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "try again"));
+            }
+            let n = self.input.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn detector_reads_streams() {
+        let markers: Vec<_> = MarkerDetector::new()
+            .markers(Trickle { input: INPUT.as_bytes(), reads: 0 })
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(markers[0], Marker { kind: MarkerKind::StartOfPacket, position: 10 });
+        let message = markers.iter().find(|marker| marker.kind == MarkerKind::StartOfMessage);
+        assert_eq!(message.map(|marker| marker.position), Some(29));
+    }
+
+    #[test]
+    fn detector_keeps_bounded_history() {
+        let mut detector = MarkerDetector::new();
+        let count = detector.feed(&[b'a'; 100_000]).count();
+        assert_eq!(count, 0);
+        assert!(detector.history.len() <= 14);
+    }
+}
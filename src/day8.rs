@@ -1,7 +1,12 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 
-struct Map {
-    cells: Vec<Vec<u8>>,
+use crate::day8_fast;
+
+pub struct Map {
+    pub cells: Vec<Vec<u8>>,
 }
 
 impl Map {
@@ -15,12 +20,93 @@ impl Map {
         self.cells.len()
     }
 
-    pub fn inner_cells(&self) -> impl Iterator<Item=Cell> {
-        (1..self.width() - 1).flat_map(move |x| (1..self.height() - 1).map(move |y| Cell { map: self, x, y }))
+    /// Number of trees on the border of the forest.
+    pub fn edges(&self) -> usize {
+        match (self.width(), self.height()) {
+            (1, height) => height,
+            (width, 1) => width,
+            (width, height) => 2 * (width + height) - 4,
+        }
+    }
+
+    pub fn inner_cells(&self) -> impl Iterator<Item=Cell<'_>> {
+        (1..self.width().saturating_sub(1))
+            .flat_map(move |x| (1..self.height().saturating_sub(1)).map(move |y| Cell { map: self, x, y }))
+    }
+
+    #[inline]
+    pub fn cell(&self, x: usize, y: usize) -> Cell<'_> {
+        Cell { map: self, x, y }
+    }
+
+    /// For every tree, the sides of the forest it can be seen from.
+    pub fn visibility(&self) -> Vec<Vec<Sides>> {
+        day8_fast::visibility(self)
+    }
+
+    pub fn scenic_scores(&self) -> Vec<Vec<usize>> {
+        day8_fast::scenic_scores(self)
+    }
+
+    /// Draw the forest with visible trees showing their height and hidden ones as `.`.
+    pub fn render_visibility(&self) -> String {
+        self.visibility()
+            .iter()
+            .zip(&self.cells)
+            .map(|(sides, heights)| {
+                sides.iter().zip(heights).map(|(sides, height)| {
+                    if sides.is_empty() { '.' } else { (b'0' + height) as char }
+                }).collect::<String>() + "\n"
+            })
+            .collect()
+    }
+
+    /// Draw the scenic scores bucketed to a digit, `9` being the best spot of the forest.
+    pub fn render_scores(&self) -> String {
+        let scores = self.scenic_scores();
+        let best = scores.iter().flatten().copied().max().unwrap_or(0).max(1);
+        scores
+            .iter()
+            .map(|row| {
+                row.iter().map(|score| (b'0' + (score * 9 / best) as u8) as char).collect::<String>() + "\n"
+            })
+            .collect()
     }
 }
 
-struct Cell<'a> {
+/// Set of the sides of the forest a tree is visible from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sides(u8);
+
+impl Sides {
+    pub const LEFT: Sides = Sides(1);
+    pub const RIGHT: Sides = Sides(1 << 1);
+    pub const TOP: Sides = Sides(1 << 2);
+    pub const BOTTOM: Sides = Sides(1 << 3);
+
+    /// In the same order as the paths of a cell.
+    const ALL: [Sides; 4] = [Sides::LEFT, Sides::RIGHT, Sides::TOP, Sides::BOTTOM];
+
+    #[inline]
+    pub fn contains(self, other: Sides) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[inline]
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Sides {
+    type Output = Sides;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Sides(self.0 | rhs.0)
+    }
+}
+
+pub struct Cell<'a> {
     map: &'a Map,
     x: usize,
     y: usize,
@@ -42,35 +128,90 @@ impl<'a> Cell<'a> {
             (self.y + 1..height).map(|y| self.map.cells[y][self.x]).collect(),
         ]
     }
+
+    pub fn visible_from(&self) -> Sides {
+        self.paths()
+            .iter()
+            .zip(Sides::ALL)
+            .filter(|(path, _)| path.iter().all(|step| *step < self.value()))
+            .fold(Sides::default(), |sides, (_, side)| sides | side)
+    }
+
+    pub fn scenic_score(&self) -> usize {
+        self.paths().iter().map(|path| {
+            path.split_inclusive(|step| *step >= self.value()).next().map(|slice| slice.len()).unwrap_or(0)
+        }).product()
+    }
 }
 
 #[aoc_generator(day8)]
-fn input_generator(input: &str) -> Map {
-    Map {
-        cells: input.lines().map(|line| line.bytes().map(|b| b - b'0').collect()).collect()
+pub fn input_generator(input: &str) -> Map {
+    parse_map(input).unwrap()
+}
+
+/// Parse a forest, making sure it is a non-empty grid of digits with rows of the same length.
+pub fn parse_map(input: &str) -> Result<Map, ParsingError> {
+    let cells = input
+        .lines()
+        .enumerate()
+        .map(|(row, line)| {
+            line.chars()
+                .enumerate()
+                .map(|(column, c)| match c.to_digit(10) {
+                    Some(height) => Ok(height as u8),
+                    None => Err(ParsingError::InvalidHeight { line: row + 1, column: column + 1, found: c }),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let width = cells.first().map(Vec::len).unwrap_or(0);
+    if width == 0 {
+        return Err(ParsingError::Empty);
+    }
+    if let Some((row, line)) = cells.iter().enumerate().find(|(_, line)| line.len() != width) {
+        return Err(ParsingError::RaggedLine { line: row + 1, expected: width, found: line.len() });
+    }
+
+    Ok(Map { cells })
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ParsingError {
+    Empty,
+    InvalidHeight { line: usize, column: usize, found: char },
+    RaggedLine { line: usize, expected: usize, found: usize },
+}
+
+impl Display for ParsingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParsingError::Empty => write!(f, "empty forest"),
+            ParsingError::InvalidHeight { line, column, found } => {
+                write!(f, "line {}, column {}: invalid tree height '{}'", line, column, found)
+            }
+            ParsingError::RaggedLine { line, expected, found } => {
+                write!(f, "line {}: expected {} trees, found {}", line, expected, found)
+            }
+        }
     }
 }
 
+impl Error for ParsingError {}
+
 #[aoc(day8, part1)]
 fn solve_part1(map: &Map) -> usize {
-    let edges = 2 * (map.width() + map.height()) - 4;
-    edges + map.inner_cells().filter(|cell| {
-        cell.paths().iter().filter(|path| path.iter().all(|step| *step < cell.value())).count() > 0
-    }).count()
+    map.edges() + map.inner_cells().filter(|cell| !cell.visible_from().is_empty()).count()
 }
 
 #[aoc(day8, part2)]
 fn solve_part2(map: &Map) -> usize {
-    map.inner_cells().map(|cell| {
-        cell.paths().iter().map(|path| {
-            path.split_inclusive(|step| *step >= cell.value()).next().map(|slice| slice.len()).unwrap_or(0)
-        }).product()
-    }).max().unwrap()
+    map.inner_cells().map(|cell| cell.scenic_score()).max().unwrap_or(0)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{input_generator, solve_part1, solve_part2};
+    use super::{input_generator, parse_map, ParsingError, Sides, solve_part1, solve_part2};
 
     static INPUT: &str = "\
 30373
@@ -100,4 +241,89 @@ mod tests {
     fn solver_part2_match_example() {
         assert_eq!(solve_part2(&input_generator(INPUT)), 8);
     }
+
+    #[test]
+    fn visibility_tells_sides() {
+        let visibility = input_generator(INPUT).visibility();
+        assert_eq!(visibility[1][1], Sides::LEFT | Sides::TOP);
+        assert_eq!(visibility[1][2], Sides::TOP | Sides::RIGHT);
+        assert!(visibility[2][2].is_empty());
+        assert_eq!(visibility[0][0], Sides::LEFT | Sides::TOP);
+        assert!(visibility[0][2].contains(Sides::TOP));
+    }
+
+    #[test]
+    fn render_visibility_hides_covered_trees() {
+        let expected = "\
+30373
+255.2
+65.32
+3.5.9
+35390
+";
+        assert_eq!(input_generator(INPUT).render_visibility(), expected);
+    }
+
+    #[test]
+    fn render_scores_buckets_to_digits() {
+        let expected = "\
+00000
+01410
+06120
+01930
+00000
+";
+        assert_eq!(input_generator(INPUT).render_scores(), expected);
+    }
+
+    #[test]
+    fn per_cell_data_handles_non_square_maps() {
+        let map = input_generator("1212\n3010");
+        assert_eq!(map.render_visibility(), "1212\n3010\n");
+        assert_eq!(map.scenic_scores(), vec![vec![0; 4]; 2]);
+    }
+
+    #[test]
+    fn parse_map_rejects_ragged_lines() {
+        assert_eq!(
+            parse_map("303\n25\n653").err(),
+            Some(ParsingError::RaggedLine { line: 2, expected: 3, found: 2 }),
+        );
+        assert_eq!(
+            parse_map("303\n2512").err(),
+            Some(ParsingError::RaggedLine { line: 2, expected: 3, found: 4 }),
+        );
+    }
+
+    #[test]
+    fn parse_map_rejects_invalid_heights_and_empty_input() {
+        assert_eq!(
+            parse_map("303\n2x5").err(),
+            Some(ParsingError::InvalidHeight { line: 2, column: 2, found: 'x' }),
+        );
+        assert_eq!(parse_map("").err(), Some(ParsingError::Empty));
+        assert_eq!(parse_map("\n").err(), Some(ParsingError::Empty));
+    }
+
+    #[test]
+    fn parse_map_accepts_crlf_and_trailing_newline() {
+        assert_eq!(parse_map("12\r\n34\r\n").unwrap().cells, vec![vec![1, 2], vec![3, 4]]);
+    }
+
+    #[test]
+    fn solvers_handle_single_row_and_column() {
+        for input in ["30373", "3\n0\n3\n7\n3", "5"] {
+            let map = input_generator(input);
+            assert_eq!(solve_part1(&map), input.lines().count() * map.width());
+            assert_eq!(solve_part2(&map), 0);
+        }
+    }
+
+    #[test]
+    fn solvers_handle_two_rows() {
+        let map = input_generator("30373\n25512");
+        assert_eq!(map.edges(), 10);
+        assert_eq!(solve_part1(&map), 10);
+        assert_eq!(solve_part2(&map), 0);
+    }
 }
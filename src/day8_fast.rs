@@ -0,0 +1,124 @@
+//! Linear time solutions, scanning every row and column once in each direction
+use aoc_runner_derive::aoc;
+
+use crate::day8::{input_generator, Map, Sides};
+
+#[aoc(day8, part1, fast)]
+pub fn solve_part1(map: &Map) -> usize {
+    visibility(map).iter().flatten().filter(|sides| !sides.is_empty()).count()
+}
+
+#[aoc(day8, part2, fast)]
+pub fn solve_part2(map: &Map) -> usize {
+    scenic_scores(map).into_iter().flatten().max().unwrap()
+}
+
+/// Every row and column, in both directions, as lists of `(x, y)` coordinates along with the side
+/// of the forest they are looked at from.
+fn lines(map: &Map) -> Vec<(Sides, Vec<(usize, usize)>)> {
+    let (width, height) = (map.width(), map.height());
+    let rows = (0..height).map(|y| {
+        ((Sides::LEFT, Sides::RIGHT), (0..width).map(|x| (x, y)).collect::<Vec<_>>())
+    });
+    let columns = (0..width).map(|x| {
+        ((Sides::TOP, Sides::BOTTOM), (0..height).map(|y| (x, y)).collect::<Vec<_>>())
+    });
+    rows.chain(columns)
+        .flat_map(|((start, end), line)| {
+            let reversed = line.iter().rev().copied().collect();
+            [(start, line), (end, reversed)]
+        })
+        .collect()
+}
+
+/// A tree is visible from a side when it is taller than the running maximum of the trees before
+/// it.
+pub(crate) fn visibility(map: &Map) -> Vec<Vec<Sides>> {
+    let mut visible = vec![vec![Sides::default(); map.width()]; map.height()];
+    for (side, line) in lines(map) {
+        let mut tallest = None;
+        for (x, y) in line {
+            let height = map.cells[y][x];
+            if tallest < Some(height) {
+                visible[y][x] = visible[y][x] | side;
+                tallest = Some(height);
+            }
+        }
+    }
+    visible
+}
+
+/// The viewing distance in a direction is the distance to the closest previous tree at least as
+/// tall, found with a stack of the previous trees in decreasing height order.
+pub(crate) fn scenic_scores(map: &Map) -> Vec<Vec<usize>> {
+    let mut scores = vec![vec![1; map.width()]; map.height()];
+    for (_, line) in lines(map) {
+        let mut stack: Vec<usize> = vec![];
+        for (i, &(x, y)) in line.iter().enumerate() {
+            let height = map.cells[y][x];
+            while let Some(&j) = stack.last() {
+                let (px, py) = line[j];
+                if map.cells[py][px] >= height {
+                    break;
+                }
+                stack.pop();
+            }
+            scores[y][x] *= stack.last().map_or(i, |j| i - j);
+            stack.push(i);
+        }
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{input_generator, scenic_scores, solve_part1, solve_part2, visibility};
+
+    static INPUT: &str = "\
+30373
+25512
+65332
+33549
+35390";
+
+    #[test]
+    fn solver_part1_match_example() {
+        assert_eq!(solve_part1(&input_generator(INPUT)), 21);
+    }
+
+    #[test]
+    fn solver_part2_match_example() {
+        assert_eq!(solve_part2(&input_generator(INPUT)), 8);
+    }
+
+    #[test]
+    fn scenic_scores_match_example() {
+        let scores = scenic_scores(&input_generator(INPUT));
+        assert_eq!(scores[1][2], 4);
+        assert_eq!(scores[3][2], 8);
+        assert_eq!(scores[0], vec![0; 5]);
+    }
+
+    #[test]
+    fn solvers_handle_degenerate_maps() {
+        for input in ["30373", "3\n0\n3\n7\n3", "5", "30373\n25512"] {
+            let map = input_generator(input);
+            assert_eq!(solve_part1(&map), map.width() * map.height());
+            assert_eq!(solve_part2(&map), 0);
+        }
+    }
+
+    #[test]
+    fn scans_agree_with_cell_paths() {
+        for input in [INPUT, "30373\n25512", "3\n0\n3\n7\n3", "5"] {
+            let map = input_generator(input);
+            let (visibility, scores) = (visibility(&map), scenic_scores(&map));
+            for y in 0..map.height() {
+                for x in 0..map.width() {
+                    assert_eq!(visibility[y][x], map.cell(x, y).visible_from(), "{},{} in {:?}", x, y, input);
+                    assert_eq!(scores[y][x], map.cell(x, y).scenic_score(), "{},{} in {:?}", x, y, input);
+                }
+            }
+        }
+    }
+}
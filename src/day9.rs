@@ -1,11 +1,20 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+use std::iter;
+
 use aoc_runner_derive::{aoc, aoc_generator};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-enum Move {
+pub enum Move {
     Up,
     Right,
     Down,
     Left,
+    UpRight,
+    DownRight,
+    DownLeft,
+    UpLeft,
 }
 
 impl Move {
@@ -15,15 +24,46 @@ impl Move {
             "R" => Move::Right,
             "D" => Move::Down,
             "L" => Move::Left,
+            "UR" => Move::UpRight,
+            "DR" => Move::DownRight,
+            "DL" => Move::DownLeft,
+            "UL" => Move::UpLeft,
             _ => unreachable!(),
         }
     }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Move::Up => "U",
+            Move::Right => "R",
+            Move::Down => "D",
+            Move::Left => "L",
+            Move::UpRight => "UR",
+            Move::DownRight => "DR",
+            Move::DownLeft => "DL",
+            Move::UpLeft => "UL",
+        }
+    }
+
+    #[inline]
+    fn delta(&self) -> (isize, isize) {
+        match self {
+            Move::Up => (0, -1),
+            Move::Right => (1, 0),
+            Move::Down => (0, 1),
+            Move::Left => (-1, 0),
+            Move::UpRight => (1, -1),
+            Move::DownRight => (1, 1),
+            Move::DownLeft => (-1, 1),
+            Move::UpLeft => (-1, -1),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Copy, Ord, PartialOrd)]
-struct Position {
-    x: isize,
-    y: isize,
+#[derive(Debug, Clone, Eq, PartialEq, Copy, Ord, PartialOrd, Hash)]
+pub struct Position {
+    pub x: isize,
+    pub y: isize,
 }
 
 impl Position {
@@ -33,12 +73,9 @@ impl Position {
     }
 
     fn apply_move(&mut self, step: Move) {
-        match step {
-            Move::Up => self.y -= 1,
-            Move::Right => self.x += 1,
-            Move::Down => self.y += 1,
-            Move::Left => self.x -= 1,
-        }
+        let (dx, dy) = step.delta();
+        self.x += dx;
+        self.y += dy;
     }
 
     fn follow(&mut self, previous: &Position) {
@@ -50,61 +87,232 @@ impl Position {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
-struct State {
+pub struct State {
     knots: Vec<Position>,
+    /// Positions of every knot after every step, step after step, when recording.
+    trajectory: Option<Vec<Position>>,
 }
 
 impl State {
-    fn new(knots_count: usize) -> Self {
+    pub fn new(knots_count: usize) -> Self {
         Self {
-            knots: vec![Position { x: 0, y: 0 }; knots_count]
+            knots: vec![Position { x: 0, y: 0 }; knots_count],
+            trajectory: None,
         }
     }
 
-    fn move_knots(&mut self, step: Move) {
+    /// A state keeping track of all the knots positions, starting with the initial ones.
+    pub fn recording(knots_count: usize) -> Self {
+        let mut state = Self::new(knots_count);
+        state.trajectory = Some(state.knots.clone());
+        state
+    }
+
+    pub fn move_knots(&mut self, step: Move) {
         self.knots[0].apply_move(step);
         for i in 1..self.knots.len() {
             let previous = self.knots[i - 1];
             self.knots[i].follow(&previous);
         }
+        if let Some(trajectory) = &mut self.trajectory {
+            trajectory.extend_from_slice(&self.knots);
+        }
+    }
+
+    /// Recorded `(step, knot, position)`, step 0 being the initial state. Empty unless recording.
+    pub fn trajectory(&self) -> impl Iterator<Item=(usize, usize, Position)> + '_ {
+        let knots_count = self.knots.len();
+        self.trajectory.iter()
+            .flatten()
+            .enumerate()
+            .map(move |(i, position)| (i / knots_count, i % knots_count, *position))
+    }
+
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "step,knot,x,y")?;
+        for (step, knot, position) in self.trajectory() {
+            writeln!(out, "{},{},{},{}", step, knot, position.x, position.y)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json_lines<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for (step, knot, position) in self.trajectory() {
+            writeln!(out, r#"{{"step":{},"knot":{},"x":{},"y":{}}}"#, step, knot, position.x, position.y)?;
+        }
+        Ok(())
     }
 }
 
-fn follow(knots_count: usize, moves: &[Move]) -> usize {
-    let mut positions = moves.iter().scan(State::new(knots_count), |state, step| {
-        state.move_knots(*step);
-        Some(state.clone())
+/// A line of the input, e.g. `R 4`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Motion {
+    step: Move,
+    count: usize,
+}
+
+impl Display for Motion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.step.as_str(), self.count)
+    }
+}
+
+impl Motion {
+    /// The single steps making up the motion.
+    pub fn moves(self) -> impl Iterator<Item=Move> {
+        iter::repeat(self.step).take(self.count)
+    }
+}
+
+/// Lazily parse motions, one line at a time.
+pub fn parse_motions(input: &str) -> impl Iterator<Item=Motion> + '_ {
+    input.lines().map(|line| {
+        let mut elems = line.split_whitespace();
+        Motion {
+            step: Move::from_str(elems.next().unwrap()),
+            count: elems.next().unwrap().parse().unwrap(),
+        }
     })
-        .map(|state| *state.knots.last().unwrap())
-        .collect::<Vec<_>>();
-    positions.sort();
-    positions.dedup();
-    positions.len()
+}
+
+/// Area drawn by the renderer, inclusive on both ends.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Bounds {
+    min: Position,
+    max: Position,
+}
+
+impl Bounds {
+    /// Smallest area holding every knot during the whole motion list.
+    pub fn of_motions(knots_count: usize, motions: &[Motion]) -> Self {
+        let origin = Position { x: 0, y: 0 };
+        let mut bounds = Self { min: origin, max: origin };
+        let mut state = State::new(knots_count);
+        for motion in motions {
+            for _ in 0..motion.count {
+                state.move_knots(motion.step);
+                for knot in &state.knots {
+                    bounds.min = Position { x: bounds.min.x.min(knot.x), y: bounds.min.y.min(knot.y) };
+                    bounds.max = Position { x: bounds.max.x.max(knot.x), y: bounds.max.y.max(knot.y) };
+                }
+            }
+        }
+        bounds
+    }
+
+    fn render<F>(&self, cell: F) -> String
+        where
+            F: Fn(Position) -> char
+    {
+        (self.min.y..=self.max.y)
+            .map(|y| (self.min.x..=self.max.x).map(|x| cell(Position { x, y })).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Draw the knots like the puzzle walkthrough: `H` for the head, then `1`, `2`... or `T` for the
+/// tail of a two knots rope, `s` for the start and `.` elsewhere. Front knots hide back ones.
+pub fn render(state: &State, bounds: &Bounds) -> String {
+    let label = |i: usize| match i {
+        0 => 'H',
+        _ if state.knots.len() == 2 => 'T',
+        _ => char::from_digit((i % 10) as u32, 10).unwrap(),
+    };
+    bounds.render(|position| {
+        match state.knots.iter().position(|knot| *knot == position) {
+            Some(i) => label(i),
+            None if position == (Position { x: 0, y: 0 }) => 's',
+            None => '.',
+        }
+    })
+}
+
+/// Draw the positions visited by the tail with `#`, the start being drawn as `s`.
+pub fn render_visited(visited: &HashSet<Position>, bounds: &Bounds) -> String {
+    bounds.render(|position| {
+        if position == (Position { x: 0, y: 0 }) {
+            's'
+        } else if visited.contains(&position) {
+            '#'
+        } else {
+            '.'
+        }
+    })
+}
+
+/// The whole walkthrough: the initial state, the state after each motion, then the positions
+/// visited by the tail.
+pub fn walkthrough(knots_count: usize, motions: &[Motion]) -> String {
+    let bounds = Bounds::of_motions(knots_count, motions);
+    let mut state = State::new(knots_count);
+    let mut visited = HashSet::from([*state.knots.last().unwrap()]);
+    let mut output = format!("== Initial State ==\n\n{}\n\n", render(&state, &bounds));
+
+    for motion in motions {
+        for _ in 0..motion.count {
+            state.move_knots(motion.step);
+            visited.insert(*state.knots.last().unwrap());
+        }
+        output += &format!("== {} ==\n\n{}\n\n", motion, render(&state, &bounds));
+    }
+
+    output + &format!("== Visited ==\n\n{}", render_visited(&visited, &bounds))
+}
+
+/// Count the positions visited by the tail, consuming moves one by one so that only the
+/// visited set grows with the input.
+pub fn follow<I>(knots_count: usize, moves: I) -> usize
+    where
+        I: IntoIterator<Item=Move>
+{
+    let mut state = State::new(knots_count);
+    let mut visited = HashSet::new();
+    for step in moves {
+        state.move_knots(step);
+        visited.insert(*state.knots.last().unwrap());
+    }
+    visited.len()
+}
+
+/// Number of distinct positions visited by each knot, head first.
+pub fn visited_per_knot<I>(knots_count: usize, moves: I) -> Vec<usize>
+    where
+        I: IntoIterator<Item=Move>
+{
+    let mut state = State::new(knots_count);
+    let mut visited = vec![HashSet::new(); knots_count];
+    for step in moves {
+        state.move_knots(step);
+        for (knot, visited) in state.knots.iter().zip(visited.iter_mut()) {
+            visited.insert(*knot);
+        }
+    }
+    visited.iter().map(HashSet::len).collect()
 }
 
 #[aoc_generator(day9)]
-fn input_generator(input: &str) -> Vec<Move> {
-    input.lines().flat_map(|line| {
-        let mut elems = line.split_whitespace();
-        vec![Move::from_str(elems.next().unwrap()); elems.next().unwrap().parse().unwrap()]
-    }).collect()
+fn input_generator(input: &str) -> Vec<Motion> {
+    parse_motions(input).collect()
 }
 
 #[aoc(day9, part1)]
-fn solve_part1(moves: &[Move]) -> usize {
-    follow(2, moves)
+fn solve_part1(motions: &[Motion]) -> usize {
+    follow(2, motions.iter().copied().flat_map(Motion::moves))
 }
 
 #[aoc(day9, part2)]
-fn solve_part2(moves: &[Move]) -> usize {
-    follow(10, moves)
+fn solve_part2(motions: &[Motion]) -> usize {
+    follow(10, motions.iter().copied().flat_map(Motion::moves))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::iter;
+
     use crate::day9::Move;
 
-    use super::{input_generator, solve_part1, solve_part2};
+    use super::{follow, input_generator, Motion, parse_motions, solve_part1, solve_part2, State, visited_per_knot, walkthrough};
 
     static INPUT1: &str = "\
 R 4
@@ -129,32 +337,17 @@ U 20";
     #[test]
     fn input_generator_builds_vec() {
         let expected = vec![
-            Move::Right,
-            Move::Right,
-            Move::Right,
-            Move::Right,
-            Move::Up,
-            Move::Up,
-            Move::Up,
-            Move::Up,
-            Move::Left,
-            Move::Left,
-            Move::Left,
-            Move::Down,
-            Move::Right,
-            Move::Right,
-            Move::Right,
-            Move::Right,
-            Move::Down,
-            Move::Left,
-            Move::Left,
-            Move::Left,
-            Move::Left,
-            Move::Left,
-            Move::Right,
-            Move::Right,
+            Motion { step: Move::Right, count: 4 },
+            Motion { step: Move::Up, count: 4 },
+            Motion { step: Move::Left, count: 3 },
+            Motion { step: Move::Down, count: 1 },
+            Motion { step: Move::Right, count: 4 },
+            Motion { step: Move::Down, count: 1 },
+            Motion { step: Move::Left, count: 5 },
+            Motion { step: Move::Right, count: 2 },
         ];
         assert_eq!(input_generator(INPUT1), expected);
+        assert_eq!(expected[0].moves().collect::<Vec<_>>(), vec![Move::Right; 4]);
     }
 
     #[test]
@@ -166,4 +359,122 @@ U 20";
     fn solver_part2_match_example() {
         assert_eq!(solve_part2(&input_generator(INPUT2)), 36);
     }
+
+    #[test]
+    fn walkthrough_draws_each_motion() {
+        let output = walkthrough(2, &parse_motions(INPUT1).collect::<Vec<_>>());
+        let mut sections = output.split("\n\n").skip(1).step_by(2);
+        assert_eq!(sections.next().unwrap(), "\
+......
+......
+......
+......
+H.....");
+        assert_eq!(sections.next().unwrap(), "\
+......
+......
+......
+......
+s..TH.");
+        assert_eq!(sections.next().unwrap(), "\
+....H.
+....T.
+......
+......
+s.....");
+        assert_eq!(sections.last().unwrap(), "\
+..##..
+...##.
+.####.
+....#.
+s###..");
+    }
+
+    #[test]
+    fn walkthrough_draws_ten_knots() {
+        let output = walkthrough(10, &parse_motions(INPUT2).collect::<Vec<_>>());
+        let sections: Vec<_> = output.split("\n\n").collect();
+        assert_eq!(sections[2], "== R 5 ==");
+        assert_eq!(sections[3].lines().nth(15).unwrap(), "...........54321H.........");
+        assert_eq!(sections.last().unwrap(), &"\
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+#.........................
+#.............###.........
+#............#...#........
+.#..........#.....#.......
+..#..........#.....#......
+...#........#.......#.....
+....#......s.........#....
+.....#..............#.....
+......#............#......
+.......#..........#.......
+........#........#........
+.........########.........");
+    }
+
+    #[test]
+    fn follow_consumes_lazy_moves() {
+        assert_eq!(follow(10, parse_motions(INPUT2).flat_map(Motion::moves)), 36);
+        assert_eq!(follow(2, iter::repeat(Move::Right).take(100_000)), 100_000);
+    }
+
+    #[test]
+    fn diagonal_moves_drag_the_rope() {
+        let motions = parse_motions("UR 3\nDL 1\nDR 2").collect::<Vec<_>>();
+        assert_eq!(motions[0], Motion { step: Move::UpRight, count: 3 });
+        assert_eq!(motions[2].to_string(), "DR 2");
+        let moves = || motions.iter().copied().flat_map(Motion::moves);
+        assert_eq!(visited_per_knot(3, moves()), vec![5, 4, 3]);
+        assert_eq!(follow(3, moves()), 3);
+    }
+
+    #[test]
+    fn visited_per_knot_ends_with_tail() {
+        let moves = || input_generator(INPUT2).into_iter().flat_map(Motion::moves);
+        let visited = visited_per_knot(10, moves());
+        assert_eq!(visited[9], 36);
+        assert_eq!(visited[1], follow(2, moves()));
+        assert!(visited.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn recording_state_exports_trajectory() {
+        let mut state = State::recording(2);
+        for step in parse_motions("R 2\nU 1").flat_map(Motion::moves) {
+            state.move_knots(step);
+        }
+
+        let mut csv = vec![];
+        state.write_csv(&mut csv).unwrap();
+        let expected = "\
+step,knot,x,y
+0,0,0,0
+0,1,0,0
+1,0,1,0
+1,1,0,0
+2,0,2,0
+2,1,1,0
+3,0,2,-1
+3,1,1,0
+";
+        assert_eq!(String::from_utf8(csv).unwrap(), expected);
+
+        let mut json = vec![];
+        state.write_json_lines(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.lines().count(), 8);
+        assert_eq!(json.lines().nth(6).unwrap(), r#"{"step":3,"knot":0,"x":2,"y":-1}"#);
+        let value: serde_json::Value = serde_json::from_str(json.lines().last().unwrap()).unwrap();
+        assert_eq!(value["knot"], 1);
+
+        assert_eq!(State::new(2).trajectory().count(), 0);
+    }
 }
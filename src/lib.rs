@@ -2,22 +2,25 @@ extern crate core;
 
 use aoc_runner_derive::aoc_lib;
 
-mod utils;
+pub mod utils;
 mod day1;
 mod day2;
 mod day2_pedantic;
 mod day3;
 mod day4;
 mod day3_bitset;
-mod day5;
-mod day6;
+pub mod day5;
+pub mod day6;
 mod day7;
-mod day8;
-mod day9;
+pub mod day8;
+mod day8_fast;
+pub mod day9;
 mod day10;
-mod day11;
+pub mod day11;
 mod day12;
-mod day13;
-mod day14;
+pub mod day13;
+mod day13_zero_copy;
+pub mod day14;
+mod day14_flood;
 
 aoc_lib! { year = 2022 }
@@ -1,28 +1,63 @@
-use std::convert::Infallible;
-use std::fmt::Debug;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
 use std::str::FromStr;
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
 pub struct Point<T: Hash + Clone> {
     pub x: T,
     pub y: T,
 }
 
-impl<T, E> FromStr for Point<T>
+impl<T> FromStr for Point<T>
     where
-        T: FromStr<Err=E> + Hash + Clone,
-        E: Debug
+        T: FromStr + Hash + Clone,
 {
-    type Err = Infallible;
+    type Err = ParsePointError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut parts = s.split(',');
-        Ok(
-            Self {
-                x: parts.next().unwrap().parse().unwrap(),
-                y: parts.next().unwrap().parse().unwrap(),
-            }
-        )
+        let mut coordinate = |name: &'static str| {
+            let part = parts.next()
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .ok_or(ParsePointError::MissingCoordinate(name))?;
+            part.parse().map_err(|_| ParsePointError::InvalidCoordinate(part.to_string()))
+        };
+
+        let point = Self {
+            x: coordinate("x")?,
+            y: coordinate("y")?,
+        };
+
+        match parts.next() {
+            Some(extra) => Err(ParsePointError::UnexpectedInput(extra.to_string())),
+            None => Ok(point),
+        }
+    }
+}
+
+impl<T: Display + Hash + Clone> Display for Point<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ParsePointError {
+    MissingCoordinate(&'static str),
+    InvalidCoordinate(String),
+    UnexpectedInput(String),
+}
+
+impl Display for ParsePointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParsePointError::MissingCoordinate(name) => write!(f, "missing {} coordinate", name),
+            ParsePointError::InvalidCoordinate(part) => write!(f, "invalid coordinate '{}'", part),
+            ParsePointError::UnexpectedInput(part) => write!(f, "unexpected input ',{}'", part),
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Error for ParsePointError {}